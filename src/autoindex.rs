@@ -0,0 +1,133 @@
+use std::time::SystemTime;
+
+use tokio::fs;
+
+use crate::config::{AutoindexFormat, SymlinkPolicy};
+use crate::httpdate::fmt_http_date;
+use crate::path::{percent_encode_path, same_owner};
+
+/// 目录列表中的一项
+pub struct Entry {
+    name: String,
+    is_dir: bool,
+    size: u64,
+    modified: Option<SystemTime>,
+}
+
+/// 读取目录内容：跳过以 "." 开头的隐藏项与符号链接策略拒绝的链接，目录在前，同类按名称排序
+pub async fn read_entries(dir: &str, policy: SymlinkPolicy) -> std::io::Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    let mut reader = fs::read_dir(dir).await?;
+    while let Some(item) = reader.next_entry().await? {
+        let Ok(name) = item.file_name().into_string() else {
+            continue;
+        };
+        if name.starts_with('.') {
+            continue;
+        }
+        let Ok(link) = fs::symlink_metadata(item.path()).await else {
+            continue;
+        };
+        // 跟随符号链接取得目标的类型与大小，失效链接以及静态文件处理会拒绝的链接不列出
+        let meta = if link.file_type().is_symlink() {
+            if policy == SymlinkPolicy::Deny {
+                continue;
+            }
+            let Ok(target) = fs::metadata(item.path()).await else {
+                continue;
+            };
+            if policy == SymlinkPolicy::OwnerMatch && !same_owner(&link, &target) {
+                continue;
+            }
+            target
+        } else {
+            link
+        };
+        entries.push(Entry {
+            name,
+            is_dir: meta.is_dir(),
+            size: meta.len(),
+            modified: meta.modified().ok(),
+        });
+    }
+    entries.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.name.cmp(&b.name)));
+    Ok(entries)
+}
+
+/// 按格式渲染目录列表，返回 Content-Type 与正文
+pub fn render(format: AutoindexFormat, path: &str, entries: &[Entry]) -> (&'static str, String) {
+    match format {
+        AutoindexFormat::Html => ("text/html; charset=utf-8", render_html(path, entries)),
+        AutoindexFormat::Json => ("application/json", render_json(entries)),
+    }
+}
+
+fn render_html(path: &str, entries: &[Entry]) -> String {
+    let title = format!("Index of {}", escape_html(path));
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>{0}</title></head>\n<body>\n<h1>{0}</h1>\n<table>\n",
+        title
+    );
+    html.push_str("<tr><th>Name</th><th>Last Modified</th><th>Size</th></tr>\n");
+    if path != "/" {
+        html.push_str("<tr><td><a href=\"../\">../</a></td><td></td><td>-</td></tr>\n");
+    }
+    for entry in entries {
+        let suffix = if entry.is_dir { "/" } else { "" };
+        let size = if entry.is_dir {
+            "-".to_string()
+        } else {
+            entry.size.to_string()
+        };
+        html.push_str(&format!(
+            "<tr><td><a href=\"{}{}\">{}{}</a></td><td>{}</td><td>{}</td></tr>\n",
+            href(&entry.name),
+            suffix,
+            escape_html(&entry.name),
+            suffix,
+            entry.modified.map(fmt_http_date).unwrap_or_default(),
+            size
+        ));
+    }
+    html.push_str("</table>\n</body>\n</html>\n");
+    html
+}
+
+fn render_json(entries: &[Entry]) -> String {
+    let items: Vec<serde_json::Value> = entries
+        .iter()
+        .map(|entry| {
+            let mut item = serde_json::json!({
+                "name": entry.name,
+                "type": if entry.is_dir { "directory" } else { "file" },
+                "mtime": entry.modified.map(fmt_http_date),
+            });
+            if !entry.is_dir {
+                item["size"] = entry.size.into();
+            }
+            item
+        })
+        .collect();
+    serde_json::Value::Array(items).to_string()
+}
+
+/// 链接目标：百分号编码，":" 也编码以免被当成 URL scheme
+fn href(name: &str) -> String {
+    escape_html(&percent_encode_path(name).replace(':', "%3A").replace('/', "%2F"))
+}
+
+/// 转义 HTML 特殊字符
+pub fn escape_html(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
@@ -0,0 +1,112 @@
+use std::io::Write;
+
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use crate::config::CompressionConfig;
+use crate::encoding::{Encoding, accepted_encodings};
+use crate::http::{Request, Version};
+
+/// brotli 的滑动窗口（2^22 字节），与命令行工具的默认值一致
+const BROTLI_LGWIN: u32 = 22;
+
+/// 判断响应能否动态压缩，能则返回与客户端协商出的编码
+///
+/// 压缩结果只能用 chunked 发送，HTTP/1.0 客户端不支持，因此不压缩。
+/// content_length 未知（如上游的 chunked 响应）时不受 min_length 限制。
+pub fn negotiate(
+    config: &CompressionConfig,
+    request: &Request,
+    content_type: &str,
+    content_length: Option<u64>,
+) -> Option<Encoding> {
+    if request.version != Version::Http11 || !config.allows_type(content_type) {
+        return None;
+    }
+    if content_length.is_some_and(|len| len < config.min_length) {
+        return None;
+    }
+    accepted_encodings(request.header("accept-encoding")).into_iter().next()
+}
+
+/// 各编码的流式压缩器，压缩结果先写入内部的 Vec
+enum Encoder {
+    Gzip(GzEncoder<Vec<u8>>),
+    Brotli(Box<brotli::CompressorWriter<Vec<u8>>>),
+    Zstd(zstd::stream::write::Encoder<'static, Vec<u8>>),
+}
+
+/// 动态压缩的响应体：输入原始数据，压缩结果按 chunked 编码写给客户端
+pub struct CompressedBody {
+    encoder: Encoder,
+}
+
+impl CompressedBody {
+    /// level 按各编码的取值范围截断：gzip 1-9，brotli 0-11，zstd 1-22
+    pub fn new(encoding: Encoding, level: u32) -> Result<Self, std::io::Error> {
+        let encoder = match encoding {
+            Encoding::Gzip => Encoder::Gzip(GzEncoder::new(Vec::new(), Compression::new(level.clamp(1, 9)))),
+            Encoding::Brotli => Encoder::Brotli(Box::new(brotli::CompressorWriter::new(
+                Vec::new(),
+                4096,
+                level.min(11),
+                BROTLI_LGWIN,
+            ))),
+            Encoding::Zstd => Encoder::Zstd(zstd::stream::write::Encoder::new(Vec::new(), level.clamp(1, 22) as i32)?),
+        };
+        Ok(Self { encoder })
+    }
+
+    /// 压缩一段数据，已产生的输出作为一个 chunk 写出
+    pub async fn write<W>(&mut self, stream: &mut W, data: &[u8]) -> Result<(), std::io::Error>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        let output = match &mut self.encoder {
+            Encoder::Gzip(e) => {
+                e.write_all(data)?;
+                std::mem::take(e.get_mut())
+            }
+            Encoder::Brotli(e) => {
+                e.write_all(data)?;
+                std::mem::take(e.get_mut())
+            }
+            Encoder::Zstd(e) => {
+                e.write_all(data)?;
+                std::mem::take(e.get_mut())
+            }
+        };
+        write_chunk(stream, &output).await
+    }
+
+    /// 结束压缩流，写出剩余输出与结尾的 0 长度 chunk
+    pub async fn finish<W>(self, stream: &mut W) -> Result<(), std::io::Error>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        let output = match self.encoder {
+            Encoder::Gzip(e) => e.finish()?,
+            // into_inner 会以 FINISH 操作刷出 brotli 流的结尾
+            Encoder::Brotli(e) => e.into_inner(),
+            Encoder::Zstd(e) => e.finish()?,
+        };
+        write_chunk(stream, &output).await?;
+        stream.write_all(b"0\r\n\r\n").await
+    }
+}
+
+/// 写出一个 chunk，空数据会被误认为结尾，因此跳过
+async fn write_chunk<W>(stream: &mut W, data: &[u8]) -> Result<(), std::io::Error>
+where
+    W: AsyncWrite + Unpin,
+{
+    if data.is_empty() {
+        return Ok(());
+    }
+    let mut chunk = Vec::with_capacity(data.len() + 16);
+    chunk.extend_from_slice(format!("{:x}\r\n", data.len()).as_bytes());
+    chunk.extend_from_slice(data);
+    chunk.extend_from_slice(b"\r\n");
+    stream.write_all(&chunk).await
+}
@@ -0,0 +1,165 @@
+use std::collections::HashMap;
+use std::fs::Metadata;
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use sha2::{Digest, Sha256};
+use tokio::fs::File;
+use tokio::io::AsyncReadExt;
+
+use crate::http::Request;
+use crate::httpdate::parse_http_date;
+
+/// 内容哈希缓存中最多保存的文件数，超过时清空重建
+const HASH_CACHE_MAX: usize = 4096;
+
+/// 内容哈希缓存：路径 -> (文件标识, ETag)
+type HashCache = Mutex<HashMap<String, (FileStamp, String)>>;
+
+/// 判断文件是否变化的标识：完整精度的修改时间与长度，Unix 下再加上 inode 与 ctime，
+/// 同一秒内的修改或替换成同样大小的新文件都能察觉
+#[derive(PartialEq)]
+struct FileStamp {
+    modified: Option<SystemTime>,
+    len: u64,
+    #[cfg(unix)]
+    ino: u64,
+    #[cfg(unix)]
+    ctime: (i64, i64),
+}
+
+impl FileStamp {
+    fn new(meta: &Metadata) -> Self {
+        #[cfg(unix)]
+        use std::os::unix::fs::MetadataExt;
+        Self {
+            modified: meta.modified().ok(),
+            len: meta.len(),
+            #[cfg(unix)]
+            ino: meta.ino(),
+            #[cfg(unix)]
+            ctime: (meta.ctime(), meta.ctime_nsec()),
+        }
+    }
+}
+
+/// 条件请求的评估结果
+pub enum Precondition {
+    /// 条件满足，正常返回
+    Proceed,
+    /// 返回 304 Not Modified
+    NotModified,
+    /// 返回 412 Precondition Failed
+    Failed,
+}
+
+/// 由修改时间与长度生成的 ETag，例如 "6543a1b2-1f40"
+pub fn metadata_etag(modified: Option<SystemTime>, len: u64) -> String {
+    let secs = modified
+        .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_secs());
+    format!("\"{:x}-{:x}\"", secs, len)
+}
+
+/// 由文件内容 SHA-256 生成的强 ETag；按路径与文件标识缓存，文件变化后重新计算
+pub async fn content_etag(file: &mut File, path: &str, meta: &Metadata) -> std::io::Result<String> {
+    static CACHE: OnceLock<HashCache> = OnceLock::new();
+    let cache = CACHE.get_or_init(|| Mutex::new(HashMap::new()));
+    let stamp = FileStamp::new(meta);
+    if let Some((cached_stamp, etag)) = cache.lock().unwrap().get(path)
+        && *cached_stamp == stamp
+    {
+        return Ok(etag.clone());
+    }
+
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buffer).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+    }
+    let digest: String = hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect();
+    let etag = format!("\"{}\"", digest);
+
+    let mut cache = cache.lock().unwrap();
+    if cache.len() >= HASH_CACHE_MAX {
+        cache.clear();
+    }
+    cache.insert(path.to_string(), (stamp, etag.clone()));
+    Ok(etag)
+}
+
+/// 按 RFC 9110 第 13.2.2 节的顺序评估 If-Match、If-Unmodified-Since、If-None-Match 与 If-Modified-Since
+pub fn evaluate(request: &Request, etag: Option<&str>, modified: Option<SystemTime>) -> Precondition {
+    let safe = request.method == "GET" || request.method == "HEAD";
+
+    if let Some(value) = request.header("if-match") {
+        if !etag_list_matches(value, etag, true) {
+            return Precondition::Failed;
+        }
+    } else if let Some(date) = request.header("if-unmodified-since").and_then(parse_http_date)
+        && modified.is_some_and(|modified| modified > date)
+    {
+        return Precondition::Failed;
+    }
+
+    if let Some(value) = request.header("if-none-match") {
+        if etag_list_matches(value, etag, false) {
+            return if safe {
+                Precondition::NotModified
+            } else {
+                Precondition::Failed
+            };
+        }
+    } else if safe
+        && let Some(date) = request.header("if-modified-since").and_then(parse_http_date)
+        && modified.is_some_and(|modified| modified <= date)
+    {
+        return Precondition::NotModified;
+    }
+
+    Precondition::Proceed
+}
+
+/// If-Range 是否与当前文件一致：实体标签按强比较，日期必须与 Last-Modified 完全相同
+pub fn if_range_matches(request: &Request, etag: Option<&str>, modified: Option<SystemTime>) -> bool {
+    let Some(value) = request.header("if-range") else {
+        return true;
+    };
+    let value = value.trim();
+    if value.starts_with('"') || value.starts_with("W/") {
+        return etag.is_some_and(|etag| strong_eq(value, etag));
+    }
+    match (parse_http_date(value), modified) {
+        (Some(date), Some(modified)) => date == modified,
+        _ => false,
+    }
+}
+
+/// 判断 If-Match / If-None-Match 的列表是否命中当前 ETag；"*" 匹配任何存在的表示
+fn etag_list_matches(value: &str, etag: Option<&str>, strong: bool) -> bool {
+    let Some(etag) = etag else {
+        return value.trim() == "*";
+    };
+    value.split(',').map(str::trim).any(|candidate| {
+        candidate == "*"
+            || if strong {
+                strong_eq(candidate, etag)
+            } else {
+                weak_eq(candidate, etag)
+            }
+    })
+}
+
+/// 强比较：两者都不是弱标签且完全相同
+fn strong_eq(a: &str, b: &str) -> bool {
+    !a.starts_with("W/") && !b.starts_with("W/") && a == b
+}
+
+/// 弱比较：去掉 W/ 前缀后相同即可
+fn weak_eq(a: &str, b: &str) -> bool {
+    a.trim_start_matches("W/") == b.trim_start_matches("W/")
+}
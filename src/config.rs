@@ -1,20 +1,568 @@
 use std::collections::HashMap;
 
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer};
 use tokio::fs;
 
+use crate::cidr::Cidr;
+use crate::mime::{MimeTypes, with_charset};
+use crate::try_files::parse_status;
+
 /// 应用配置，包含监听地址、静态根目录、反向代理路由与连接池参数
 #[derive(Debug, Deserialize, Clone)]
 pub struct AppConfig {
-    /// 监听地址，例如 "127.0.0.1:8080"
-    pub listen_addr: String,
+    /// 监听列表，每个 worker 会同时在所有地址上接受连接；
+    /// 兼容旧的 listen_addr 键与单个地址的写法
+    #[serde(alias = "listen_addr", deserialize_with = "deserialize_listen")]
+    pub listen: Vec<ListenConfig>,
     /// 静态文件根目录
     pub root_path: String,
-    /// 反向代理路由前缀到上游地址的映射
-    pub upstreams: HashMap<String, String>,
+    /// 反向代理路由前缀到上游的映射，值可写成地址字符串或带选项的对象；
+    /// 以 @ 开头的键是命名上游，不按路径匹配，只作为 try_files 的兜底
+    #[serde(deserialize_with = "deserialize_upstreams")]
+    pub upstreams: HashMap<String, UpstreamConfig>,
     /// 连接池配置
     #[serde(default)]
     pub pool: PoolConfig,
+    /// 每个 worker 同时处理的最大客户端连接数，达到后暂停 accept
+    #[serde(default = "default_worker_connections")]
+    pub worker_connections: usize,
+    /// worker 启动时把 RLIMIT_NOFILE 提升到该值（仅 Unix）
+    #[serde(default)]
+    pub worker_rlimit_nofile: Option<u64>,
+    /// 长连接上等待下一个请求的秒数，0 表示不保持连接
+    #[serde(default = "default_keepalive_timeout")]
+    pub keepalive_timeout: u64,
+    /// 单个长连接上最多处理的请求数
+    #[serde(default = "default_keepalive_requests")]
+    pub keepalive_requests: usize,
+    /// 读取完整请求头的秒数，超时返回 408
+    #[serde(default = "default_client_timeout")]
+    pub client_header_timeout: u64,
+    /// 读取请求体时两次读操作之间的最大间隔秒数，超时返回 408
+    #[serde(default = "default_client_timeout")]
+    pub client_body_timeout: u64,
+    /// 向客户端发送响应时两次写操作之间的最大间隔秒数，超时关闭连接
+    #[serde(default = "default_client_timeout")]
+    pub send_timeout: u64,
+    /// 读取请求头使用的缓冲区数量与大小
+    #[serde(default)]
+    pub large_client_header_buffers: HeaderBuffersConfig,
+    /// 请求体最大字节数，可写成数字或 "10m" 这类带单位的字符串，0 表示不限制
+    #[serde(default = "default_client_max_body_size", deserialize_with = "deserialize_size")]
+    pub client_max_body_size: u64,
+    /// 请求目标（URI）最大长度，未设置时只受头部缓冲区限制
+    #[serde(default)]
+    pub max_uri_length: Option<usize>,
+    /// 请求头总大小上限，未设置时只受头部缓冲区限制
+    #[serde(default)]
+    pub max_header_size: Option<usize>,
+    /// 静态文件路径上遇到符号链接时的处理方式
+    #[serde(default)]
+    pub symlinks: SymlinkPolicy,
+    /// 请求目录时依次尝试的索引文件
+    #[serde(default = "default_index")]
+    pub index: Vec<String>,
+    /// 目录下没有索引文件时生成目录列表
+    #[serde(default)]
+    pub autoindex: bool,
+    /// 目录列表的格式：html 或 json
+    #[serde(default)]
+    pub autoindex_format: AutoindexFormat,
+    /// 客户端接受时改为发送同目录下预压缩的 .br/.zst/.gz 文件
+    #[serde(default)]
+    pub gzip_static: bool,
+    /// 按 Accept-Encoding 动态压缩静态文件与上游响应
+    #[serde(default)]
+    pub compression: CompressionConfig,
+    /// 为静态文件生成 ETag（默认由修改时间与长度得出）
+    #[serde(default = "default_etag")]
+    pub etag: bool,
+    /// 改用文件内容的 SHA-256 作为强 ETag，按修改时间与长度缓存
+    #[serde(default)]
+    pub etag_content_hash: bool,
+    /// 在 Linux 上用 sendfile(2) 发送静态文件，关闭时使用缓冲复制
+    #[serde(default = "default_sendfile")]
+    pub sendfile: bool,
+    /// 单次 sendfile 调用最多发送的字节数，避免一个大文件长时间占住 worker，0 表示不限制
+    #[serde(default = "default_sendfile_max_chunk", deserialize_with = "deserialize_size")]
+    pub sendfile_max_chunk: u64,
+    /// 额外加载的 nginx 格式 mime.types 文件，其中的条目覆盖内置表
+    #[serde(default)]
+    pub mime_types_file: Option<String>,
+    /// 按后缀覆盖 MIME 类型，如 {"mjs": "text/javascript"}，优先级最高
+    #[serde(default)]
+    pub mime_types: HashMap<String, String>,
+    /// 后缀不在映射表中时使用的类型
+    #[serde(default = "default_type")]
+    pub default_type: String,
+    /// 为文本类型追加的 charset，如 "utf-8"，未设置时不追加
+    #[serde(default)]
+    pub charset: Option<String>,
+    /// 加载配置时由内置表、mime_types_file 与 mime_types 合并而成
+    #[serde(skip)]
+    pub mime: MimeTypes,
+    /// 按路径前缀覆盖的路由级配置，最长前缀优先
+    #[serde(default)]
+    pub locations: HashMap<String, LocationConfig>,
+}
+
+/// 路由级配置，未设置的项沿用全局值
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct LocationConfig {
+    #[serde(default, deserialize_with = "deserialize_opt_size")]
+    pub client_max_body_size: Option<u64>,
+    #[serde(default)]
+    pub max_uri_length: Option<usize>,
+    #[serde(default)]
+    pub max_header_size: Option<usize>,
+    /// 允许的请求方法（类似 nginx 的 limit_except），未设置时不限制；允许 GET 即允许 HEAD
+    #[serde(default)]
+    pub limit_except: Option<Vec<String>>,
+    #[serde(default)]
+    pub symlinks: Option<SymlinkPolicy>,
+    #[serde(default)]
+    pub index: Option<Vec<String>>,
+    #[serde(default)]
+    pub autoindex: Option<bool>,
+    #[serde(default)]
+    pub autoindex_format: Option<AutoindexFormat>,
+    #[serde(default)]
+    pub gzip_static: Option<bool>,
+    /// 覆盖 compression.enabled
+    #[serde(default)]
+    pub compression: Option<bool>,
+    /// 依次尝试的路径，如 ["$uri", "$uri/", "/index.html"]；最后一项也可以是 "=404"（200-599）或命名上游 "@name"
+    #[serde(default)]
+    pub try_files: Option<Vec<String>>,
+}
+
+/// 目录列表格式
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AutoindexFormat {
+    #[default]
+    Html,
+    Json,
+}
+
+/// 静态文件的符号链接策略
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SymlinkPolicy {
+    /// 总是跟随
+    #[default]
+    Follow,
+    /// 路径上任何一级是符号链接都拒绝
+    Deny,
+    /// 仅当链接与目标属于同一用户时跟随
+    OwnerMatch,
+}
+
+impl LocationConfig {
+    /// 判断请求方法是否被本路由允许
+    pub fn allows_method(&self, method: &str) -> bool {
+        match &self.limit_except {
+            None => true,
+            Some(methods) => methods.iter().any(|allowed| {
+                let allowed = allowed.to_ascii_uppercase();
+                allowed == method || (method == "HEAD" && allowed == "GET")
+            }),
+        }
+    }
+
+    /// 405 响应中 Allow 头部的取值
+    pub fn allow_header(&self) -> String {
+        let mut methods: Vec<String> = self
+            .limit_except
+            .iter()
+            .flatten()
+            .map(|method| method.to_ascii_uppercase())
+            .collect();
+        if methods.iter().any(|m| m == "GET") && !methods.iter().any(|m| m == "HEAD") {
+            methods.push("HEAD".to_string());
+        }
+        methods.join(", ")
+    }
+}
+
+/// 某个请求最终生效的大小限制
+pub struct RequestLimits {
+    /// 0 表示不限制
+    pub client_max_body_size: u64,
+    pub max_uri_length: Option<usize>,
+    pub max_header_size: Option<usize>,
+}
+
+impl AppConfig {
+    /// 按最长前缀匹配路由级配置
+    pub fn location(&self, path: &str) -> Option<&LocationConfig> {
+        self.locations
+            .iter()
+            .filter(|(prefix, _)| path.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, location)| location)
+    }
+
+    /// 计算请求路径上生效的符号链接策略
+    pub fn symlink_policy(&self, path: &str) -> SymlinkPolicy {
+        self.location(path).and_then(|l| l.symlinks).unwrap_or(self.symlinks)
+    }
+
+    /// 计算请求路径上生效的索引文件列表
+    pub fn index_files(&self, path: &str) -> &[String] {
+        self.location(path).and_then(|l| l.index.as_deref()).unwrap_or(&self.index)
+    }
+
+    /// 请求路径上开启目录列表时返回其格式，未开启时返回 None
+    pub fn autoindex_format(&self, path: &str) -> Option<AutoindexFormat> {
+        let location = self.location(path);
+        let enabled = location.and_then(|l| l.autoindex).unwrap_or(self.autoindex);
+        enabled.then(|| location.and_then(|l| l.autoindex_format).unwrap_or(self.autoindex_format))
+    }
+
+    /// 请求路径上是否发送预压缩文件
+    pub fn gzip_static(&self, path: &str) -> bool {
+        self.location(path).and_then(|l| l.gzip_static).unwrap_or(self.gzip_static)
+    }
+
+    /// 请求路径上开启动态压缩时返回压缩配置，未开启时返回 None
+    pub fn compression(&self, path: &str) -> Option<&CompressionConfig> {
+        let enabled = self.location(path).and_then(|l| l.compression).unwrap_or(self.compression.enabled);
+        enabled.then_some(&self.compression)
+    }
+
+    /// 按文件名后缀得出 Content-Type，文本类型按 charset 配置追加字符集
+    pub fn content_type(&self, filename: &str) -> String {
+        let mime = self.mime.lookup(filename).unwrap_or(&self.default_type);
+        with_charset(mime, self.charset.as_deref())
+    }
+
+    /// 计算请求路径上生效的大小限制
+    pub fn request_limits(&self, path: &str) -> RequestLimits {
+        let location = self.location(path);
+        RequestLimits {
+            client_max_body_size: location
+                .and_then(|l| l.client_max_body_size)
+                .unwrap_or(self.client_max_body_size),
+            max_uri_length: location.and_then(|l| l.max_uri_length).or(self.max_uri_length),
+            max_header_size: location.and_then(|l| l.max_header_size).or(self.max_header_size),
+        }
+    }
+}
+
+/// 大小的原始写法：字节数或带 k/m/g 单位的字符串
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SizeValue {
+    Bytes(u64),
+    Text(String),
+}
+
+impl SizeValue {
+    fn into_bytes(self) -> Result<u64, String> {
+        let text = match self {
+            SizeValue::Bytes(n) => return Ok(n),
+            SizeValue::Text(text) => text,
+        };
+        let trimmed = text.trim();
+        let (number, unit) = match trimmed.char_indices().find(|(_, c)| !c.is_ascii_digit()) {
+            Some((i, _)) => trimmed.split_at(i),
+            None => (trimmed, ""),
+        };
+        let multiplier = match unit.to_ascii_lowercase().as_str() {
+            "" => 1,
+            "k" => 1024,
+            "m" => 1024 * 1024,
+            "g" => 1024 * 1024 * 1024,
+            _ => return Err(format!("invalid size {:?}", text)),
+        };
+        number
+            .parse::<u64>()
+            .map(|n| n.saturating_mul(multiplier))
+            .map_err(|_| format!("invalid size {:?}", text))
+    }
+}
+
+fn deserialize_size<'de, D>(deserializer: D) -> Result<u64, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    SizeValue::deserialize(deserializer)?
+        .into_bytes()
+        .map_err(serde::de::Error::custom)
+}
+
+fn deserialize_opt_size<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_size(deserializer).map(Some)
+}
+
+/// 请求头缓冲区限制，与 nginx 的 large_client_header_buffers 含义一致：
+/// 请求行和每个头字段行都不能超过 size，整个头部不能超过 number * size
+#[derive(Debug, Deserialize, Clone)]
+pub struct HeaderBuffersConfig {
+    #[serde(default = "default_header_buffers_number")]
+    pub number: usize,
+    #[serde(default = "default_header_buffers_size")]
+    pub size: usize,
+}
+
+impl Default for HeaderBuffersConfig {
+    fn default() -> Self {
+        Self {
+            number: default_header_buffers_number(),
+            size: default_header_buffers_size(),
+        }
+    }
+}
+
+/// 单个监听项，可写成地址字符串或带选项的对象
+#[derive(Debug, Deserialize, Clone)]
+pub struct ListenConfig {
+    /// 监听地址，例如 "127.0.0.1:8080"、"[::]:80" 或 "unix:/run/mini_nginx.sock"
+    pub addr: String,
+    /// 是否开启 SO_REUSEPORT（仅 Unix），开启时各 worker 各自绑定端口，关闭时由 master 绑定一次再继承给 worker
+    #[serde(default = "default_listen_reuseport")]
+    pub reuseport: bool,
+    /// IPv6 地址是否只接受 IPv6 连接
+    #[serde(default)]
+    pub ipv6only: bool,
+    /// Unix 套接字文件权限（八进制字符串，例如 "0660"）
+    #[serde(default)]
+    pub mode: Option<String>,
+    /// Unix 套接字文件属主（用户名或 uid）
+    #[serde(default)]
+    pub owner: Option<String>,
+    /// Unix 套接字文件属组（组名或 gid）
+    #[serde(default)]
+    pub group: Option<String>,
+    /// 绑定前是否清理无人监听的残留套接字文件
+    #[serde(default = "default_listen_remove_stale")]
+    pub remove_stale: bool,
+    /// listen(2) 的等待队列长度
+    #[serde(default = "default_listen_backlog")]
+    pub backlog: i32,
+    /// 是否对接受的连接开启 TCP_NODELAY
+    #[serde(default)]
+    pub tcp_nodelay: bool,
+    /// 对接受的连接开启 SO_KEEPALIVE 及其探测参数
+    #[serde(default)]
+    pub so_keepalive: Option<KeepaliveConfig>,
+    /// SO_RCVBUF 大小（字节）
+    #[serde(default)]
+    pub rcvbuf: Option<usize>,
+    /// SO_SNDBUF 大小（字节）
+    #[serde(default)]
+    pub sndbuf: Option<usize>,
+    /// TCP_DEFER_ACCEPT 等待秒数（仅 Linux），数据到达后才唤醒 accept
+    #[serde(default)]
+    pub defer_accept_secs: Option<u32>,
+    /// TCP_FASTOPEN 队列长度（仅 Linux）
+    #[serde(default)]
+    pub fastopen: Option<u32>,
+    /// 是否要求连接以 PROXY protocol v1/v2 头部开始
+    #[serde(default)]
+    pub proxy_protocol: bool,
+    /// 允许发送 PROXY 头部的来源网段，TCP 监听开启 proxy_protocol 时不能为空
+    #[serde(default)]
+    pub proxy_protocol_trusted: Vec<Cidr>,
+}
+
+/// TCP keepalive 探测参数，未设置的项沿用系统默认值
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct KeepaliveConfig {
+    /// 连接空闲多久后开始探测（秒）
+    #[serde(default)]
+    pub idle_secs: Option<u64>,
+    /// 探测间隔（秒）
+    #[serde(default)]
+    pub interval_secs: Option<u64>,
+    /// 判定连接失效前的探测次数
+    #[serde(default)]
+    pub count: Option<u32>,
+}
+
+impl ListenConfig {
+    /// 若为 Unix 套接字监听项，返回套接字文件路径
+    pub fn unix_path(&self) -> Option<&str> {
+        self.addr.strip_prefix("unix:")
+    }
+}
+
+impl Default for ListenConfig {
+    fn default() -> Self {
+        Self {
+            addr: String::new(),
+            reuseport: default_listen_reuseport(),
+            ipv6only: false,
+            mode: None,
+            owner: None,
+            group: None,
+            remove_stale: default_listen_remove_stale(),
+            backlog: default_listen_backlog(),
+            tcp_nodelay: false,
+            so_keepalive: None,
+            rcvbuf: None,
+            sndbuf: None,
+            defer_accept_secs: None,
+            fastopen: None,
+            proxy_protocol: false,
+            proxy_protocol_trusted: Vec::new(),
+        }
+    }
+}
+
+/// listen 项的原始写法：字符串简写或完整对象
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ListenEntry {
+    Addr(String),
+    Full(ListenConfig),
+}
+
+/// listen 的原始写法：单个监听项或列表
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ListenEntries {
+    One(ListenEntry),
+    Many(Vec<ListenEntry>),
+}
+
+/// 反序列化 listen 列表，把字符串简写展开为默认选项
+fn deserialize_listen<'de, D>(deserializer: D) -> Result<Vec<ListenConfig>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let entries = match ListenEntries::deserialize(deserializer)? {
+        ListenEntries::One(entry) => vec![entry],
+        ListenEntries::Many(entries) => entries,
+    };
+    Ok(entries
+        .into_iter()
+        .map(|entry| match entry {
+            ListenEntry::Addr(addr) => ListenConfig {
+                addr,
+                ..ListenConfig::default()
+            },
+            ListenEntry::Full(listen) => listen,
+        })
+        .collect())
+}
+
+fn default_listen_reuseport() -> bool {
+    true
+}
+
+fn default_listen_remove_stale() -> bool {
+    true
+}
+
+fn default_listen_backlog() -> i32 {
+    1024
+}
+
+/// 单个上游配置
+#[derive(Debug, Deserialize, Clone)]
+pub struct UpstreamConfig {
+    /// 上游地址，例如 "127.0.0.1:9000"
+    pub addr: String,
+    /// 新建上游连接时先发送的 PROXY protocol 头部版本
+    #[serde(default)]
+    pub proxy_protocol: Option<ProxyProtocolVersion>,
+    /// 先完整读取 chunked 请求体，再以 Content-Length 发给不支持 chunked 的上游
+    #[serde(default)]
+    pub buffer_chunked_body: bool,
+    /// 缓冲 chunked 请求体的字节上限，与 client_max_body_size 同时生效，不能为 0
+    #[serde(default = "default_buffered_body_max_size", deserialize_with = "deserialize_size")]
+    pub buffered_body_max_size: u64,
+    /// 与上游建立连接的超时秒数
+    #[serde(default = "default_upstream_timeout")]
+    pub connect_timeout: u64,
+    /// 向上游发送请求时两次写操作之间的最大间隔秒数
+    #[serde(default = "default_upstream_timeout")]
+    pub send_timeout: u64,
+    /// 读取上游响应时两次读操作之间的最大间隔秒数
+    #[serde(default = "default_upstream_timeout")]
+    pub read_timeout: u64,
+}
+
+/// PROXY protocol 版本
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ProxyProtocolVersion {
+    V1,
+    V2,
+}
+
+/// upstreams 值的原始写法：字符串简写或完整对象
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum UpstreamEntry {
+    Addr(String),
+    Full(UpstreamConfig),
+}
+
+/// 反序列化 upstreams 映射，把字符串简写展开为默认选项
+fn deserialize_upstreams<'de, D>(deserializer: D) -> Result<HashMap<String, UpstreamConfig>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let entries = HashMap::<String, UpstreamEntry>::deserialize(deserializer)?;
+    Ok(entries
+        .into_iter()
+        .map(|(route, entry)| {
+            let upstream = match entry {
+                UpstreamEntry::Addr(addr) => UpstreamConfig {
+                    addr,
+                    proxy_protocol: None,
+                    buffer_chunked_body: false,
+                    buffered_body_max_size: default_buffered_body_max_size(),
+                    connect_timeout: default_upstream_timeout(),
+                    send_timeout: default_upstream_timeout(),
+                    read_timeout: default_upstream_timeout(),
+                },
+                UpstreamEntry::Full(upstream) => upstream,
+            };
+            (route, upstream)
+        })
+        .collect())
+}
+
+/// 动态压缩配置，来自 config.json 的 compression 字段
+#[derive(Debug, Deserialize, Clone)]
+pub struct CompressionConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// 允许压缩的 MIME 类型（不含参数）
+    #[serde(default = "default_compression_types")]
+    pub types: Vec<String>,
+    /// 已知长度小于该值的响应不压缩
+    #[serde(default = "default_compression_min_length", deserialize_with = "deserialize_size")]
+    pub min_length: u64,
+    /// 压缩级别，超出某种编码的取值范围时按该编码的上下限截断
+    #[serde(default = "default_compression_level")]
+    pub level: u32,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            types: default_compression_types(),
+            min_length: default_compression_min_length(),
+            level: default_compression_level(),
+        }
+    }
+}
+
+impl CompressionConfig {
+    /// Content-Type 是否在允许压缩的类型中
+    pub fn allows_type(&self, content_type: &str) -> bool {
+        let mime = content_type.split(';').next().unwrap_or("").trim();
+        self.types.iter().any(|t| t.eq_ignore_ascii_case(mime))
+    }
 }
 
 /// 连接池配置，来自 config.json 的 pool 字段
@@ -41,6 +589,87 @@ impl Default for PoolConfig {
     }
 }
 
+fn default_worker_connections() -> usize {
+    1024
+}
+
+fn default_keepalive_timeout() -> u64 {
+    75
+}
+
+fn default_keepalive_requests() -> usize {
+    1000
+}
+
+fn default_client_timeout() -> u64 {
+    60
+}
+
+fn default_upstream_timeout() -> u64 {
+    60
+}
+
+fn default_client_max_body_size() -> u64 {
+    1024 * 1024
+}
+
+fn default_buffered_body_max_size() -> u64 {
+    8 * 1024 * 1024
+}
+
+fn default_index() -> Vec<String> {
+    vec!["index.html".to_string()]
+}
+
+fn default_compression_types() -> Vec<String> {
+    [
+        "text/html",
+        "text/css",
+        "text/plain",
+        "text/xml",
+        "text/javascript",
+        "application/javascript",
+        "application/json",
+        "application/xml",
+        "image/svg+xml",
+    ]
+    .iter()
+    .map(|t| t.to_string())
+    .collect()
+}
+
+fn default_compression_min_length() -> u64 {
+    256
+}
+
+fn default_compression_level() -> u32 {
+    6
+}
+
+fn default_type() -> String {
+    "application/octet-stream".to_string()
+}
+
+fn default_etag() -> bool {
+    true
+}
+
+fn default_sendfile() -> bool {
+    true
+}
+
+fn default_sendfile_max_chunk() -> u64 {
+    2 * 1024 * 1024
+}
+
+fn default_header_buffers_number() -> usize {
+    4
+}
+
+fn default_header_buffers_size() -> usize {
+    8192
+}
+
 fn default_pool_max_size() -> usize {
     128
 }
@@ -56,6 +685,47 @@ fn default_pool_probe_timeout_ms() -> u64 {
 /// 从指定路径读取并解析配置文件
 pub async fn load_config(path: &str) -> Result<AppConfig, Box<dyn std::error::Error>> {
     let config_content = fs::read_to_string(path).await?;
-    let config: AppConfig = serde_json::from_str(&config_content)?;
+    let mut config: AppConfig = serde_json::from_str(&config_content)?;
+    if config.listen.is_empty() {
+        return Err("config: listen must contain at least one address".into());
+    }
+    // 不限制来源时任何客户端都能伪造地址，因此要求显式列出信任网段
+    if let Some(listen) = config
+        .listen
+        .iter()
+        .find(|l| l.proxy_protocol && l.unix_path().is_none() && l.proxy_protocol_trusted.is_empty())
+    {
+        return Err(format!("config: listen {}: proxy_protocol requires proxy_protocol_trusted", listen.addr).into());
+    }
+    // client_max_body_size 为 0 时不限制，缓冲请求体必须另有上限
+    if let Some((route, _)) = config
+        .upstreams
+        .iter()
+        .find(|(_, upstream)| upstream.buffer_chunked_body && upstream.buffered_body_max_size == 0)
+    {
+        return Err(format!("config: upstreams.{}: buffered_body_max_size must not be 0", route).into());
+    }
+    for (prefix, location) in &config.locations {
+        let Some(fallback) = location.try_files.as_ref().and_then(|entries| entries.last()) else {
+            continue;
+        };
+        if let Some(code) = fallback.strip_prefix('=')
+            && parse_status(code).is_none()
+        {
+            return Err(format!("config: locations.{}: invalid try_files status {:?}", prefix, fallback).into());
+        }
+        if fallback.starts_with('@') && !config.upstreams.contains_key(fallback) {
+            return Err(format!("config: locations.{}: unknown upstream {:?} in try_files", prefix, fallback).into());
+        }
+    }
+    if let Some(path) = &config.mime_types_file {
+        let text = fs::read_to_string(path)
+            .await
+            .map_err(|e| format!("config: mime_types_file {}: {}", path, e))?;
+        config.mime.merge_nginx(&text)?;
+    }
+    for (ext, mime) in &config.mime_types {
+        config.mime.insert(ext, mime);
+    }
     Ok(config)
 }
@@ -0,0 +1,73 @@
+/// 支持的内容编码，声明顺序即 q 值相同时的服务端偏好
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Brotli,
+    Zstd,
+    Gzip,
+}
+
+impl Encoding {
+    pub const ALL: [Encoding; 3] = [Encoding::Brotli, Encoding::Zstd, Encoding::Gzip];
+
+    /// Content-Encoding 中使用的名称
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Zstd => "zstd",
+            Encoding::Gzip => "gzip",
+        }
+    }
+
+    /// 预压缩文件的后缀
+    pub fn extension(self) -> &'static str {
+        match self {
+            Encoding::Brotli => ".br",
+            Encoding::Zstd => ".zst",
+            Encoding::Gzip => ".gz",
+        }
+    }
+}
+
+/// 按 Accept-Encoding 列出客户端接受的编码：q 值高的在前，q 值相同按服务端偏好；q=0 表示拒绝
+pub fn accepted_encodings(header: Option<&str>) -> Vec<Encoding> {
+    let Some(header) = header else {
+        return Vec::new();
+    };
+    let mut weights: Vec<(String, f32)> = Vec::new();
+    for item in header.split(',') {
+        let mut params = item.split(';').map(|p| p.trim_matches([' ', '\t']));
+        let coding = params.next().unwrap_or("").to_ascii_lowercase();
+        if coding.is_empty() {
+            continue;
+        }
+        let mut q = Some(1.0);
+        for param in params {
+            if let Some((key, value)) = param.split_once('=')
+                && key.trim().eq_ignore_ascii_case("q")
+            {
+                q = value.trim().parse::<f32>().ok().filter(|q| (0.0..=1.0).contains(q));
+            }
+        }
+        // q 值非法的项直接忽略
+        if let Some(q) = q {
+            weights.push((coding, q));
+        }
+    }
+
+    let weight = |name: &str| weights.iter().find(|(coding, _)| coding == name).map(|(_, q)| *q);
+    let wildcard = weight("*");
+    let mut accepted: Vec<(Encoding, f32)> = Encoding::ALL
+        .iter()
+        .filter_map(|&encoding| {
+            let explicit = match encoding {
+                Encoding::Gzip => weight("gzip").or_else(|| weight("x-gzip")),
+                _ => weight(encoding.name()),
+            };
+            let q = explicit.or(wildcard)?;
+            (q > 0.0).then_some((encoding, q))
+        })
+        .collect();
+    // 稳定排序，q 值相同的保持服务端偏好顺序
+    accepted.sort_by(|a, b| b.1.total_cmp(&a.1));
+    accepted.into_iter().map(|(encoding, _)| encoding).collect()
+}
@@ -1,193 +1,809 @@
+use std::net::IpAddr;
 use std::sync::Arc;
 
 use tokio::fs;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpStream;
+use tokio::time::{self, Duration};
 
-use crate::config::AppConfig;
-use crate::mime::get_mime_type;
+use crate::conditional::{Precondition, content_etag, evaluate, if_range_matches, metadata_etag};
+use crate::autoindex::{self, read_entries};
+use crate::compress::{CompressedBody, negotiate};
+use crate::config::{AppConfig, AutoindexFormat, CompressionConfig, RequestLimits, SymlinkPolicy, UpstreamConfig};
+use crate::encoding::accepted_encodings;
+use crate::http::{
+    BodyError, ChunkedBody, Request, check_framing, discard_body, error_response, error_response_with_headers,
+    find_head_end, parse_fields, read_more, read_request, reason_phrase, split_field, split_head_lines,
+};
+use crate::httpdate::{fmt_http_date, truncate_to_secs};
+use crate::path::{normalize_path, percent_encode_path, symlinks_allowed};
 use crate::pool::ConnectionPool;
+use crate::proxy_protocol::build_proxy_header;
+use crate::range::{Multipart, RangeRequest, parse_range};
+use crate::sendfile::{send_file, send_file_compressed};
+use crate::stream::{ClientStream, PeerAddr, TimeoutStream};
+use crate::try_files::{self, TryFiles};
 
-/// 处理单个客户端连接：解析请求并分发到静态文件或反向代理
-pub async fn handle_client(mut stream: TcpStream, config: Arc<AppConfig>, pool: ConnectionPool) {
-    let mut buffer = [0; 1024];
+/// 静态文件支持的请求方法
+const STATIC_METHODS: [&str; 3] = ["GET", "HEAD", "OPTIONS"];
 
-    // 读取首包请求，用于解析请求行
-    let size = match stream.read(&mut buffer).await {
-        Ok(n) if n == 0 => return,
-        Ok(n) => n,
-        Err(_) => return,
+/// 客户端连接：套接字、连接级读缓冲与对端地址
+struct Client {
+    /// 写操作受 send_timeout 限制，读请求体期间受 client_body_timeout 限制
+    stream: TimeoutStream<ClientStream>,
+    /// 请求头解析后剩下的是已读到的请求体前缀及后续管线化请求
+    buffer: Vec<u8>,
+    peer: PeerAddr,
+    /// 发送过 PROXY 头部的上游连接只属于本客户端，留到后续请求复用，客户端断开时随之关闭
+    bound_upstream: Option<BoundUpstream>,
+}
+
+/// 绑定到某个客户端的上游连接，以及建立时发送的 PROXY 头部
+struct BoundUpstream {
+    addr: String,
+    proxy_header: Vec<u8>,
+    stream: TcpStream,
+}
+
+/// 处理单个客户端连接：按顺序解析请求（支持长连接与管线化）并分发到静态文件或反向代理
+pub async fn handle_client(
+    stream: ClientStream,
+    peer: PeerAddr,
+    config: Arc<AppConfig>,
+    pool: ConnectionPool,
+) {
+    let mut stream = TimeoutStream::new(stream);
+    stream.set_write_timeout(Some(Duration::from_secs(config.send_timeout)));
+    let mut client = Client {
+        stream,
+        buffer: Vec::with_capacity(4096),
+        peer,
+        bound_upstream: None,
     };
+    let keepalive_timeout = Duration::from_secs(config.keepalive_timeout);
+    let header_timeout = Duration::from_secs(config.client_header_timeout);
+    let body_timeout = Duration::from_secs(config.client_body_timeout);
+    let mut served = 0usize;
 
-    let req_str = String::from_utf8_lossy(&buffer[..size]);
-    let first_line = req_str.lines().next().unwrap_or("");
-    let path = first_line.split_whitespace().nth(1).unwrap_or("/");
+    loop {
+        // 首个请求之后，等待下一个请求首字节的时间受 keepalive_timeout 限制
+        if served > 0 && client.buffer.is_empty() {
+            match time::timeout(keepalive_timeout, read_more(&mut client.stream, &mut client.buffer)).await {
+                Ok(Ok(n)) if n > 0 => {}
+                _ => break,
+            }
+        }
 
-    println!("Request: {} (Path: {})", first_line, path);
+        // 整个请求头必须在 client_header_timeout 内读完，防止慢速发送头部占住连接
+        let read = read_request(
+            &mut client.stream,
+            &mut client.buffer,
+            &config.large_client_header_buffers,
+        );
+        let result = match time::timeout(header_timeout, read).await {
+            Ok(result) => result,
+            Err(_) => {
+                eprintln!("Client header timeout from {}", client.peer);
+                // 一个字节都没收到的空闲连接直接关闭
+                if !client.buffer.is_empty() {
+                    let _ = client.stream.write_all(&error_response(408)).await;
+                }
+                break;
+            }
+        };
+        let request = match result {
+            Ok(Some(request)) => request,
+            Ok(None) => break,
+            Err(e) => {
+                eprintln!("Invalid request from {}: {}", client.peer, e);
+                if let Some(status) = e.status() {
+                    let _ = client.stream.write_all(&error_response(status)).await;
+                }
+                break;
+            }
+        };
+        served += 1;
 
-    // 根据路由前缀匹配上游地址
-    let mut matched_upstream = None;
-    for (route, upstream_addr) in &config.upstreams {
-        if path.starts_with(route) {
-            matched_upstream = Some((route, upstream_addr));
+        // 星号形式的请求目标只能用于 OPTIONS
+        if request.target == "*" && request.method != "OPTIONS" {
+            eprintln!("Rejecting {} * from {} with 400", request.method, client.peer);
+            let _ = client.stream.write_all(&error_response(400)).await;
             break;
         }
-    }
 
-    if let Some((route, upstream_addr)) = matched_upstream {
-        handle_reverse_proxy(stream, &mut buffer, size, upstream_addr, route, pool).await;
-    } else {
-        handle_static_file(&mut stream, &mut buffer, size, &config.root_path).await;
+        // 路由与路由级配置都按解码、规范化后的路径匹配，查询串不参与；越出根目录的路径直接拒绝
+        let normalized = if request.target == "*" {
+            Some(request.target.clone())
+        } else {
+            normalize_path(request.path())
+        };
+        let Some(normalized) = normalized else {
+            eprintln!("Rejecting unsafe path {:?} from {}", request.target, client.peer);
+            let _ = client.stream.write_all(&error_response(400)).await;
+            break;
+        };
+        let path = normalized.as_str();
+
+        println!("Request: {} (Path: {}) from {}", request.request_line(), path, client.peer);
+
+        // 按路由生效的大小限制检查请求，超限时请求体未读，只能关闭连接
+        let limits = config.request_limits(path);
+        if let Some(status) = check_request_limits(&request, &limits) {
+            eprintln!("Rejecting request from {} with {}", client.peer, status);
+            let _ = client.stream.write_all(&error_response(status)).await;
+            break;
+        }
+
+        // 路由通过 limit_except 限制了请求方法时，其它方法直接返回 405
+        let location = config.location(path);
+        if let Some(location) = location
+            && !location.allows_method(&request.method)
+        {
+            eprintln!("Rejecting {} from {} with 405", request.method, client.peer);
+            let allow = location.allow_header();
+            let _ = client
+                .stream
+                .write_all(&error_response_with_headers(405, &[("Allow", &allow)]))
+                .await;
+            break;
+        }
+
+        // 是否保持连接：客户端意愿、单连接请求数上限与 keepalive_timeout 共同决定
+        let keep_alive = request.wants_keep_alive()
+            && served < config.keepalive_requests
+            && config.keepalive_timeout > 0;
+
+        // 根据路由前缀匹配上游地址
+        let mut matched_upstream = None;
+        for (route, upstream) in &config.upstreams {
+            if path.starts_with(route) {
+                matched_upstream = Some((route, upstream));
+                break;
+            }
+        }
+
+        // 只在处理请求期间（读取请求体）启用 client_body_timeout
+        client.stream.set_read_timeout(Some(body_timeout));
+        let reusable = if let Some((route, upstream)) = matched_upstream {
+            let target = upstream_target(path, route, request.query());
+            let compression = config.compression(path);
+            handle_reverse_proxy(&mut client, &request, upstream, &target, &pool, &limits, compression, keep_alive).await
+        } else {
+            // 配置了 try_files 的路由先按顺序查找实际要发送的路径
+            let resolved = match location.and_then(|l| l.try_files.as_deref()) {
+                Some(entries) => try_files::resolve(&config.root_path, entries, path).await,
+                None => TryFiles::Path(path.to_string()),
+            };
+            match resolved {
+                TryFiles::Path(file) => {
+                    handle_static_file(&mut client, &request, &file, &config, &limits, keep_alive).await
+                }
+                TryFiles::Status(status) => match drain_body(&mut client, &request, &limits).await {
+                    Ok(()) => write_simple_response(&mut client.stream, &request, status, &[], keep_alive).await,
+                    Err(e) => reject_body(&mut client, e).await,
+                },
+                TryFiles::Upstream(name) => {
+                    // 命名上游在加载配置时已校验存在，请求路径原样转发
+                    let upstream = &config.upstreams[name];
+                    let target = upstream_target(path, "", request.query());
+                    let compression = config.compression(path);
+                    handle_reverse_proxy(&mut client, &request, upstream, &target, &pool, &limits, compression, keep_alive)
+                        .await
+                }
+            }
+        };
+        client.stream.set_read_timeout(None);
+        if !reusable {
+            break;
+        }
     }
+
+    let _ = client.stream.shutdown().await;
 }
 
 /// 反向代理处理：改写请求行并转发上下游数据
+///
+/// 返回客户端连接能否继续处理下一个请求。
+#[allow(clippy::too_many_arguments)]
 async fn handle_reverse_proxy(
-    mut stream: TcpStream,
-    buffer: &mut [u8],
-    size: usize,
-    upstream_addr: &str,
-    route: &str,
-    pool: ConnectionPool,
-) {
+    client: &mut Client,
+    request: &Request,
+    upstream: &UpstreamConfig,
+    target: &str,
+    pool: &ConnectionPool,
+    limits: &RequestLimits,
+    compression: Option<&CompressionConfig>,
+    keep_alive: bool,
+) -> bool {
+    let upstream_addr = upstream.addr.as_str();
     println!("--> Forwarding to upstream {}...", upstream_addr);
 
-    // 从连接池获取上游连接
-    match pool.get(upstream_addr).await {
-        Ok(mut upstream_stream) => {
-            // 改写请求行，把路由前缀转成根路径
-            let request_bytes = &buffer[..size];
-            let new_request_bytes = rewrite_request_line(request_bytes, route);
+    // 需要 PROXY 头部的上游连接不进连接池，只在同一客户端内复用
+    let proxy_header = upstream
+        .proxy_protocol
+        .map(|version| build_proxy_header(version, &client.peer, client.stream.get_ref().local_addr()));
 
-            if let Err(e) = upstream_stream.write_all(&new_request_bytes).await {
-                eprintln!("Failed to write to upstream: {}", e);
-                return;
-            }
+    // 上游不接受 chunked 请求体时，先在本地读完整个请求体，大小同时受两项上限约束（0 表示不限制）
+    let buffered_body = if request.is_chunked() && upstream.buffer_chunked_body {
+        let limit = match limits.client_max_body_size {
+            0 => upstream.buffered_body_max_size,
+            limit => limit.min(upstream.buffered_body_max_size),
+        };
+        match read_chunked_body(client, limit).await {
+            Ok(body) => Some(body),
+            Err(e) => return reject_body(client, e).await,
+        }
+    } else {
+        None
+    };
 
-            // 若存在请求体，继续把剩余请求体转发给上游
-            if let Some((header_end, content_length)) = request_content_length(request_bytes) {
-                let mut remaining = content_length.saturating_sub(request_bytes.len().saturating_sub(header_end));
-                let mut temp = [0u8; 4096];
-                while remaining > 0 {
-                    let n = match stream.read(&mut temp).await {
-                        Ok(0) => break,
-                        Ok(n) => n,
-                        Err(_) => break,
-                    };
-                    let to_write = n.min(remaining);
-                    if upstream_stream.write_all(&temp[..to_write]).await.is_err() {
-                        return;
-                    }
-                    remaining -= to_write;
-                }
-            }
+    // 优先复用本客户端的上游连接，否则从连接池获取，之后的读写分别受 read_timeout 与 send_timeout 限制
+    let mut bound = None;
+    if let (Some(header), Some(previous)) = (&proxy_header, client.bound_upstream.take())
+        && previous.addr == upstream_addr
+        && previous.proxy_header == *header
+        && upstream_idle(&previous.stream).await
+    {
+        bound = Some(previous.stream);
+    }
+    let connect_timeout = Duration::from_secs(upstream.connect_timeout);
+    let connected = match bound {
+        Some(stream) => Ok(stream),
+        None => pool.get(upstream_addr, proxy_header.as_deref(), connect_timeout).await,
+    };
+    let mut upstream_stream = match connected {
+        Ok(upstream_stream) => TimeoutStream::new(upstream_stream),
+        Err(e) => return fail_upstream(&mut client.stream, upstream_addr, "connect", e).await,
+    };
+    upstream_stream.set_read_timeout(Some(Duration::from_secs(upstream.read_timeout)));
+    upstream_stream.set_write_timeout(Some(Duration::from_secs(upstream.send_timeout)));
 
-            // 读取上游响应头，用于判断 keep-alive 与响应体长度
-            let response_head = match read_response_head(&mut upstream_stream).await {
-                Ok(head) => head,
-                Err(e) => {
-                    eprintln!("Failed to read response: {}", e);
-                    return;
-                }
-            };
+    // 改写请求行，并附加客户端地址
+    let mut upstream_request = build_upstream_request(request, target, &client.peer);
+    if let Some(body) = &buffered_body {
+        // 已缓冲的 chunked 请求体改用 Content-Length 发送
+        upstream_request
+            .headers
+            .retain(|(key, _)| !key.eq_ignore_ascii_case("transfer-encoding"));
+        upstream_request
+            .headers
+            .push(("Content-Length".to_string(), body.len().to_string().into_bytes()));
+    }
 
-            if stream.write_all(&response_head.header).await.is_err() {
-                return;
-            }
+    if let Err(e) = upstream_stream.write_all(&upstream_request.encode_head()).await {
+        return fail_upstream(&mut client.stream, upstream_addr, "send", e).await;
+    }
 
-            // 根据响应头选择转发方式
-            let relay_result = if response_head.info.chunked {
-                relay_chunked(&mut upstream_stream, &mut stream, response_head.body_prefix).await
-            } else if let Some(content_length) = response_head.info.content_length {
-                if !response_head.body_prefix.is_empty() {
-                    if stream.write_all(&response_head.body_prefix).await.is_err() {
-                        return;
-                    }
-                }
-                relay_content_length(
-                    &mut upstream_stream,
-                    &mut stream,
-                    content_length,
-                    response_head.body_prefix.len(),
-                )
-                .await
-            } else {
-                if !response_head.body_prefix.is_empty() {
-                    if stream.write_all(&response_head.body_prefix).await.is_err() {
-                        return;
-                    }
-                }
-                relay_until_eof(&mut upstream_stream, &mut stream).await
-            };
+    // 转发请求体：已缓冲的直接发送，chunked 边解码边重新编码，否则按 Content-Length 转发
+    let body_result = if let Some(body) = &buffered_body {
+        upstream_stream.write_all(body).await.map_err(BodyError::Upstream)
+    } else if request.is_chunked() {
+        forward_chunked_body(client, &mut upstream_stream, limits.client_max_body_size).await
+    } else if let Some(content_length) = request.content_length() {
+        forward_body(client, &mut upstream_stream, content_length).await
+    } else {
+        Ok(())
+    };
+    match body_result {
+        Ok(()) => {}
+        Err(BodyError::Upstream(e)) => return fail_upstream(&mut client.stream, upstream_addr, "send", e).await,
+        Err(e) => return reject_body(client, e).await,
+    }
 
-            match relay_result {
-                Ok(()) => {
-                    // 仅当上游明确 keep-alive 时才回收连接
-                    if response_head.info.keep_alive {
-                        pool.recycle(upstream_addr, upstream_stream);
+    let stream = &mut client.stream;
+
+    // 读取上游响应头，跳过 1xx 中间响应，用于判断 keep-alive 与响应体长度
+    let mut pending = Vec::new();
+    let response_head = loop {
+        let head = match read_response_head(&mut upstream_stream, pending).await {
+            Ok(head) => head,
+            Err(e) => return fail_upstream(stream, upstream_addr, "read", e).await,
+        };
+        if !(100..200).contains(&head.info.status) || head.info.status == 101 {
+            break head;
+        }
+        if stream.write_all(&head.header).await.is_err() {
+            return false;
+        }
+        pending = head.body_prefix;
+    };
+    let info = &response_head.info;
+    // HEAD 请求及 204/304 响应没有响应体
+    let bodyless = request.method == "HEAD" || matches!(info.status, 204 | 304);
+
+    // 动态压缩只处理上游未编码且未声明 no-transform 的 200 响应
+    let compressed = match (compression, &info.content_type) {
+        (Some(compression), Some(content_type)) if info.status == 200 && !info.encoded && !info.no_transform => {
+            negotiate(compression, request, content_type, info.content_length.map(|len| len as u64))
+        }
+        _ => None,
+    };
+
+    // 响应体只能以连接关闭界定时，客户端连接也不能复用；压缩后改用 chunked 则不受影响
+    let keep_alive = keep_alive && (bodyless || compressed.is_some() || info.chunked || info.content_length.is_some());
+
+    let mut header = response_head.header.clone();
+    if let Some(encoding) = compressed {
+        header = rewrite_for_compression(&header, encoding.name());
+    }
+    let header = rewrite_response_connection(&header, keep_alive);
+    if stream.write_all(&header).await.is_err() {
+        return false;
+    }
+
+    // 根据响应头选择转发方式
+    let relay_result = if bodyless {
+        Ok(())
+    } else if let Some(encoding) = compressed {
+        match CompressedBody::new(encoding, compression.map_or(0, |c| c.level)) {
+            Ok(body) => relay_compressed(&mut upstream_stream, stream, info, response_head.body_prefix, body).await,
+            Err(e) => Err(e),
+        }
+    } else if response_head.info.chunked {
+        relay_chunked(&mut upstream_stream, stream, response_head.body_prefix).await
+    } else if let Some(content_length) = response_head.info.content_length {
+        if !response_head.body_prefix.is_empty()
+            && stream.write_all(&response_head.body_prefix).await.is_err()
+        {
+            return false;
+        }
+        relay_content_length(
+            &mut upstream_stream,
+            stream,
+            content_length,
+            response_head.body_prefix.len(),
+        )
+        .await
+    } else {
+        if !response_head.body_prefix.is_empty()
+            && stream.write_all(&response_head.body_prefix).await.is_err()
+        {
+            return false;
+        }
+        relay_until_eof(&mut upstream_stream, stream).await
+    };
+
+    match relay_result {
+        Ok(()) => {
+            // 仅当上游明确 keep-alive 时才回收连接，带 PROXY 头部的连接留给本客户端
+            if response_head.info.keep_alive {
+                let upstream_stream = upstream_stream.into_inner();
+                match proxy_header {
+                    Some(proxy_header) => {
+                        client.bound_upstream = Some(BoundUpstream {
+                            addr: upstream_addr.to_string(),
+                            proxy_header,
+                            stream: upstream_stream,
+                        })
                     }
-                }
-                Err(e) => {
-                    eprintln!("Proxy transfer error: {}", e);
+                    None => pool.recycle(upstream_addr, upstream_stream),
                 }
             }
+            keep_alive
         }
         Err(e) => {
-            eprintln!("Failed to connect to upstream: {}", e);
-            let _ = stream
-                .write_all(b"HTTP/1.1 502 Bad Gateway\r\n\r\nUpstream down")
-                .await;
+            eprintln!("Proxy transfer error with upstream {}: {}", upstream_addr, e);
+            false
         }
     }
 }
 
 /// 静态文件处理：根据路径读取文件并构建响应
-async fn handle_static_file(stream: &mut TcpStream, buffer: &mut [u8], size: usize, root_path: &str) {
-    if size == 0 {
-        return;
+///
+/// 返回客户端连接能否继续处理下一个请求。
+async fn handle_static_file(
+    client: &mut Client,
+    request: &Request,
+    path: &str,
+    config: &AppConfig,
+    limits: &RequestLimits,
+    keep_alive: bool,
+) -> bool {
+    // 先读掉请求体，保证下一个请求从正确位置开始
+    if let Err(e) = drain_body(client, request, limits).await {
+        return reject_body(client, e).await;
+    }
+
+    // 静态文件只支持 GET、HEAD 与 OPTIONS，Allow 再按路由的 limit_except 过滤
+    let location = config.location(path);
+    let allow = STATIC_METHODS
+        .iter()
+        .copied()
+        .filter(|method| location.is_none_or(|l| l.allows_method(method)))
+        .collect::<Vec<_>>()
+        .join(", ");
+    if !STATIC_METHODS.contains(&request.method.as_str()) {
+        eprintln!("Rejecting {} from {} with 405", request.method, client.peer);
+        let _ = client
+            .stream
+            .write_all(&error_response_with_headers(405, &[("Allow", &allow)]))
+            .await;
+        return false;
+    }
+
+    let stream = &mut client.stream;
+
+    if request.method == "OPTIONS" {
+        let header = format!(
+            "HTTP/1.1 204 No Content\r\nAllow: {}\r\nConnection: {}\r\n\r\n",
+            allow,
+            connection_value(keep_alive)
+        );
+        return stream.write_all(header.as_bytes()).await.is_ok() && keep_alive;
+    }
+
+    if !symlinks_allowed(&config.root_path, path, config.symlink_policy(path)).await {
+        eprintln!("Rejecting symlink path {:?} from {}", path, client.peer);
+        let _ = stream.write_all(&error_response(403)).await;
+        return false;
+    }
+
+    let mut file_path = format!("{}{}", config.root_path, path);
+    let mut filename = path.to_string();
+
+    // 目录：缺少结尾斜杠时重定向，否则依次尝试索引文件，都没有时按配置生成目录列表
+    if fs::metadata(&file_path).await.is_ok_and(|meta| meta.is_dir()) {
+        if !path.ends_with('/') {
+            let mut location = percent_encode_path(path) + "/";
+            if let Some(query) = request.query() {
+                location.push('?');
+                location.push_str(query);
+            }
+            return write_simple_response(stream, request, 301, &[("Location", &location)], keep_alive).await;
+        }
+        match find_index(&file_path, config.index_files(path)).await {
+            Some(index) => {
+                file_path.push_str(index);
+                filename.push_str(index);
+                if !symlinks_allowed(&config.root_path, &filename, config.symlink_policy(path)).await {
+                    eprintln!("Rejecting symlink path {:?} from {}", filename, client.peer);
+                    return write_simple_response(stream, request, 403, &[], keep_alive).await;
+                }
+            }
+            None => {
+                return match config.autoindex_format(path) {
+                    Some(format) => {
+                        let policy = config.symlink_policy(path);
+                        send_autoindex(stream, request, &file_path, path, format, policy, keep_alive).await
+                    }
+                    None => write_simple_response(stream, request, 403, &[], keep_alive).await,
+                };
+            }
+        }
+    }
+
+    // gzip_static：客户端接受时改为发送预压缩的同名文件，MIME 类型仍按原文件判断
+    let gzip_static = config.gzip_static(path);
+    let mut content_encoding = None;
+    if gzip_static {
+        for encoding in accepted_encodings(request.header("accept-encoding")) {
+            let sibling = format!("{}{}", file_path, encoding.extension());
+            if fs::metadata(&sibling).await.is_ok_and(|meta| meta.is_file())
+                && symlinks_allowed(
+                    &config.root_path,
+                    &format!("{}{}", filename, encoding.extension()),
+                    config.symlink_policy(path),
+                )
+                .await
+            {
+                file_path = sibling;
+                content_encoding = Some(encoding);
+                break;
+            }
+        }
     }
 
-    let req_str = String::from_utf8_lossy(&buffer[..size]);
-    let first_line = req_str.lines().next().unwrap_or("");
-    let path = first_line.split_whitespace().nth(1).unwrap_or("/");
+    println!("Request: {} -> File: {}", request.request_line(), file_path);
+
+    // 文件存在则流式返回内容，不存在或不是普通文件则返回 404
+    let opened = match fs::File::open(&file_path).await {
+        Ok(file) => file.metadata().await.map(|meta| (file, meta)),
+        Err(e) => Err(e),
+    };
+    let (mut file, meta) = match opened {
+        Ok((file, meta)) if meta.is_file() => (file, meta),
+        _ => return write_simple_response(stream, request, 404, &[], keep_alive).await,
+    };
+    let len = meta.len();
+    // HTTP 日期只精确到秒，截断后用于 Last-Modified 与条件请求比较；内容哈希缓存使用完整精度
+    let modified = meta.modified().ok().map(truncate_to_secs);
+    let content_type = &config.content_type(&filename);
+
+    let etag = if !config.etag {
+        None
+    } else if config.etag_content_hash {
+        match content_etag(&mut file, &file_path, &meta).await {
+            Ok(etag) => Some(etag),
+            Err(e) => {
+                eprintln!("hash file error: {}", e);
+                None
+            }
+        }
+    } else {
+        Some(metadata_etag(modified, len))
+    };
+
+    // 动态压缩：没有发送预压缩文件时按类型与长度协商编码，压缩后的内容只能用弱 ETag 标识
+    let compression = config.compression(path).filter(|c| c.allows_type(content_type));
+    let compressed = match compression {
+        Some(compression) if content_encoding.is_none() => negotiate(compression, request, content_type, Some(len)),
+        _ => None,
+    };
+    let etag = match etag {
+        Some(etag) if compressed.is_some() && !etag.starts_with("W/") => Some(format!("W/{}", etag)),
+        etag => etag,
+    };
+
+    // 校验器与 Vary 同时出现在 200、206 与 304 响应中
+    let mut validators = String::new();
+    if gzip_static || compression.is_some() {
+        validators.push_str("Vary: Accept-Encoding\r\n");
+    }
+    if let Some(etag) = &etag {
+        validators.push_str(&format!("ETag: {}\r\n", etag));
+    }
+    if let Some(modified) = modified {
+        validators.push_str(&format!("Last-Modified: {}\r\n", fmt_http_date(modified)));
+    }
 
-    // 将根路径映射到 index.html
-    let filename = if path == "/" { "index.html" } else { &path[1..] };
-    let file_path = format!("{}/{}", root_path, filename);
+    // 条件请求：命中缓存返回 304，前置条件不满足返回 412，均不带正文
+    let status_line = match evaluate(request, etag.as_deref(), modified) {
+        Precondition::Proceed => None,
+        Precondition::NotModified => Some("HTTP/1.1 304 Not Modified\r\n".to_string() + &validators),
+        Precondition::Failed => Some("HTTP/1.1 412 Precondition Failed\r\nContent-Length: 0\r\n".to_string()),
+    };
+    if let Some(head) = status_line {
+        let header = format!("{}Connection: {}\r\n\r\n", head, connection_value(keep_alive));
+        return stream.write_all(header.as_bytes()).await.is_ok() && keep_alive;
+    }
 
-    println!("Request: {} -> File: {}", first_line, filename);
+    // Range 只作用于 GET；If-Range 与当前文件不一致或需要动态压缩时返回完整内容
+    let ranges = match request.header("range") {
+        Some(range) if request.method == "GET" && compressed.is_none() && if_range_matches(request, etag.as_deref(), modified) => {
+            parse_range(range, len)
+        }
+        _ => RangeRequest::Full,
+    };
 
-    // 文件存在则返回内容，不存在则返回 404
-    let (status_line, content_type, content) = match fs::read(file_path).await {
-        Ok(content) => ("HTTP/1.1 200 OK", get_mime_type(filename), content),
-        Err(_) => (
-            "HTTP/1.1 404 NOT FOUND",
-            "text/html",
-            "<h1>404 Not Found</h1>".as_bytes().to_vec(),
+    // 响应体由若干段组成：每段先写前缀，再发送文件中 [offset, offset + n) 的内容，最后写结尾
+    let mut parts: Vec<(String, u64, u64)> = Vec::new();
+    let mut trailer = String::new();
+    let (status_line, entity_headers) = match ranges {
+        RangeRequest::Full if compressed.is_some() => (
+            "HTTP/1.1 200 OK",
+            format!("Content-Type: {}\r\nTransfer-Encoding: chunked\r\n", content_type),
+        ),
+        RangeRequest::Full => {
+            parts.push((String::new(), 0, len));
+            (
+                "HTTP/1.1 200 OK",
+                format!("Content-Type: {}\r\nContent-Length: {}\r\n", content_type, len),
+            )
+        }
+        RangeRequest::Unsatisfiable => (
+            "HTTP/1.1 416 Range Not Satisfiable",
+            format!("Content-Range: bytes */{}\r\nContent-Length: 0\r\n", len),
         ),
+        RangeRequest::Partial(ranges) if ranges.len() == 1 => {
+            let (start, end) = ranges[0];
+            parts.push((String::new(), start, end - start + 1));
+            (
+                "HTTP/1.1 206 Partial Content",
+                format!(
+                    "Content-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\nContent-Length: {}\r\n",
+                    content_type,
+                    start,
+                    end,
+                    len,
+                    end - start + 1
+                ),
+            )
+        }
+        RangeRequest::Partial(ranges) => {
+            let multipart = Multipart::new(&ranges, content_type, len);
+            let headers = format!(
+                "Content-Type: multipart/byteranges; boundary={}\r\nContent-Length: {}\r\n",
+                multipart.boundary,
+                multipart.content_length()
+            );
+            parts.extend(multipart.parts.into_iter().map(|(head, start, end)| (head, start, end - start + 1)));
+            trailer = multipart.trailer;
+            ("HTTP/1.1 206 Partial Content", headers)
+        }
     };
 
-    let header = format!(
-        "{}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n",
-        status_line,
-        content_type,
-        content.len()
-    );
+    // 动态压缩的响应不支持 Range
+    let accept_ranges = if compressed.is_some() { "" } else { "Accept-Ranges: bytes\r\n" };
+    let mut header = format!("{}\r\n{}{}{}", status_line, entity_headers, accept_ranges, validators);
+    if let Some(encoding) = content_encoding.or(compressed) {
+        header.push_str(&format!("Content-Encoding: {}\r\n", encoding.name()));
+    }
+    header.push_str(&format!("Connection: {}\r\n\r\n", connection_value(keep_alive)));
 
     if let Err(e) = stream.write_all(header.as_bytes()).await {
         eprintln!("write header error: {}", e);
-        return;
+        return false;
     }
-    if let Err(e) = stream.write_all(&content).await {
-        eprintln!("write body error: {}", e);
+    // HEAD 只返回头部，Content-Length 仍是完整正文的长度
+    if request.method == "HEAD" {
+        return keep_alive;
     }
+    if let Some(encoding) = compressed {
+        let result = match CompressedBody::new(encoding, config.compression.level) {
+            Ok(body) => send_file_compressed(stream, &mut file, len, body).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            eprintln!("write body error: {}", e);
+            return false;
+        }
+        return keep_alive;
+    }
+    for (prefix, offset, n) in parts {
+        if !prefix.is_empty() && stream.write_all(prefix.as_bytes()).await.is_err() {
+            return false;
+        }
+        // 没有改写正文的响应过滤器时才能零拷贝发送
+        if let Err(e) = send_file(stream, &mut file, offset, n, config.sendfile, config.sendfile_max_chunk).await {
+            eprintln!("write body error: {}", e);
+            return false;
+        }
+    }
+    if !trailer.is_empty() && stream.write_all(trailer.as_bytes()).await.is_err() {
+        return false;
+    }
+    keep_alive
+}
+
+/// 返回第一个存在的索引文件名
+async fn find_index<'a>(dir: &str, index: &'a [String]) -> Option<&'a str> {
+    for name in index {
+        if fs::metadata(format!("{}{}", dir, name)).await.is_ok_and(|meta| meta.is_file()) {
+            return Some(name);
+        }
+    }
+    None
+}
+
+/// 生成并发送目录列表
+async fn send_autoindex(
+    stream: &mut TimeoutStream<ClientStream>,
+    request: &Request,
+    dir: &str,
+    path: &str,
+    format: AutoindexFormat,
+    policy: SymlinkPolicy,
+    keep_alive: bool,
+) -> bool {
+    let entries = match read_entries(dir, policy).await {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprintln!("read dir error: {}", e);
+            return write_simple_response(stream, request, 403, &[], keep_alive).await;
+        }
+    };
+    let (content_type, body) = autoindex::render(format, path, &entries);
+    let mut response = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: {}\r\n\r\n",
+        content_type,
+        body.len(),
+        connection_value(keep_alive)
+    )
+    .into_bytes();
+    if request.method != "HEAD" {
+        response.extend_from_slice(body.as_bytes());
+    }
+    stream.write_all(&response).await.is_ok() && keep_alive
+}
+
+/// 发送带简短 HTML 正文的响应（404、403、301 等），HEAD 请求不带正文，连接按 keep_alive 保持
+async fn write_simple_response(
+    stream: &mut TimeoutStream<ClientStream>,
+    request: &Request,
+    status: u16,
+    headers: &[(&str, &str)],
+    keep_alive: bool,
+) -> bool {
+    let mut response = format!("HTTP/1.1 {} {}\r\n", status, reason_phrase(status));
+    for (key, value) in headers {
+        response.push_str(&format!("{}: {}\r\n", key, value));
+    }
+    // 204 与 304 响应不能带响应体，也不发送 Content-Length
+    if matches!(status, 204 | 304) {
+        response.push_str(&format!("Connection: {}\r\n\r\n", connection_value(keep_alive)));
+        return stream.write_all(response.as_bytes()).await.is_ok() && keep_alive;
+    }
+    let body = format!("<h1>{} {}</h1>", status, reason_phrase(status));
+    response.push_str(&format!(
+        "Content-Type: text/html\r\nContent-Length: {}\r\nConnection: {}\r\n\r\n",
+        body.len(),
+        connection_value(keep_alive)
+    ));
+    if request.method != "HEAD" {
+        response.push_str(&body);
+    }
+    stream.write_all(response.as_bytes()).await.is_ok() && keep_alive
+}
+
+/// 读掉不需要的请求体，chunked 请求体仍受 client_max_body_size 限制
+async fn drain_body(client: &mut Client, request: &Request, limits: &RequestLimits) -> Result<(), BodyError> {
+    if request.is_chunked() {
+        read_chunked_body(client, limits.client_max_body_size).await.map(|_| ())
+    } else if let Some(length) = request.content_length() {
+        discard_body(&mut client.stream, &mut client.buffer, length)
+            .await
+            .map_err(BodyError::from)
+    } else {
+        Ok(())
+    }
+}
+
+/// 请求体读取失败：能回写状态码时回写，并关闭连接
+async fn reject_body(client: &mut Client, e: BodyError) -> bool {
+    eprintln!("Invalid request body from {}: {}", client.peer, e);
+    if let Some(status) = e.status() {
+        let _ = client.stream.write_all(&error_response(status)).await;
+    }
+    false
+}
+
+/// 上游出错：记录上游地址，超时回写 504，其它错误回写 502，并关闭客户端连接
+async fn fail_upstream(
+    stream: &mut TimeoutStream<ClientStream>,
+    upstream_addr: &str,
+    action: &str,
+    e: std::io::Error,
+) -> bool {
+    let status = if e.kind() == std::io::ErrorKind::TimedOut { 504 } else { 502 };
+    eprintln!("Upstream {} {} failed: {} (responding {})", upstream_addr, action, e, status);
+    let _ = stream.write_all(&error_response(status)).await;
+    false
+}
+
+/// 完整读取并解码 chunked 请求体
+async fn read_chunked_body(client: &mut Client, limit: u64) -> Result<Vec<u8>, BodyError> {
+    let mut body = Vec::new();
+    let mut chunked = ChunkedBody::new(limit);
+    while let Some(data) = chunked.next(&mut client.stream, &mut client.buffer).await? {
+        body.extend_from_slice(&data);
+    }
+    Ok(body)
+}
+
+/// 检查 URI 长度、请求头大小与声明的请求体大小，超限时返回对应状态码
+fn check_request_limits(request: &Request, limits: &RequestLimits) -> Option<u16> {
+    if limits.max_uri_length.is_some_and(|max| request.target.len() > max) {
+        return Some(414);
+    }
+    if limits.max_header_size.is_some_and(|max| request.head_len > max) {
+        return Some(431);
+    }
+    if limits.client_max_body_size > 0
+        && request
+            .content_length()
+            .is_some_and(|len| len as u64 > limits.client_max_body_size)
+    {
+        return Some(413);
+    }
+    None
+}
+
+/// 响应 Connection 头的取值
+fn connection_value(keep_alive: bool) -> &'static str {
+    if keep_alive { "keep-alive" } else { "close" }
 }
 
 /// 解析后的响应元信息，用于决定是否复用连接
 struct ResponseInfo {
+    status: u16,
     keep_alive: bool,
     content_length: Option<usize>,
     chunked: bool,
+    content_type: Option<String>,
+    /// 响应已带有非 identity 的 Content-Encoding
+    encoded: bool,
+    /// Cache-Control 含 no-transform，不允许代理改写响应体
+    no_transform: bool,
 }
 
 /// 响应头与已读取的响应体前缀
@@ -197,109 +813,219 @@ struct ResponseHead {
     info: ResponseInfo,
 }
 
-/// 改写请求行，保留其余请求头和内容不变
-fn rewrite_request_line(request_bytes: &[u8], route: &str) -> Vec<u8> {
-    let line_end = match request_bytes.windows(2).position(|w| w == b"\r\n") {
-        Some(end) => end,
-        None => return request_bytes.to_vec(),
-    };
+/// 生成转发给上游的请求头：替换请求目标，去掉逐跳头部，并附加客户端地址
+fn build_upstream_request(request: &Request, target: &str, peer: &PeerAddr) -> Request {
+    let mut upstream_request = request.clone();
+    upstream_request.target = target.to_string();
+    // Connection 等逐跳头部只描述客户端连接，不能影响上游连接的复用
+    upstream_request.headers.retain(|(key, _)| !is_hop_by_hop(key));
+    if let Some(ip) = peer.ip() {
+        add_forwarded_for(&mut upstream_request.headers, ip);
+    }
+    upstream_request
+}
 
-    let line = String::from_utf8_lossy(&request_bytes[..line_end]);
-    let mut parts = line.split_whitespace();
-    let method = parts.next().unwrap_or("");
-    let path = parts.next().unwrap_or("");
-    let version = parts.next().unwrap_or("");
+/// Connection 相关的逐跳头部
+fn is_hop_by_hop(name: &str) -> bool {
+    ["connection", "keep-alive", "proxy-connection"]
+        .iter()
+        .any(|h| name.eq_ignore_ascii_case(h))
+}
 
-    if method.is_empty() || version.is_empty() {
-        return request_bytes.to_vec();
+/// 把上游响应头中的 Connection 相关头替换为与客户端连接一致的值
+fn rewrite_response_connection(header: &[u8], keep_alive: bool) -> Vec<u8> {
+    let mut out = Vec::with_capacity(header.len() + 32);
+    for line in head_lines(header) {
+        if split_field(line).is_some_and(|(key, _)| is_hop_by_hop(key)) {
+            continue;
+        }
+        out.extend_from_slice(line);
+        out.extend_from_slice(b"\r\n");
     }
+    out.extend_from_slice(format!("Connection: {}\r\n\r\n", connection_value(keep_alive)).as_bytes());
+    out
+}
 
-    let new_path = if path.starts_with(route) {
-        path.replacen(route, "/", 1)
-    } else {
-        path.to_string()
-    };
-
-    let new_line = format!("{} {} {}", method, new_path, version);
-    let mut out = Vec::with_capacity(request_bytes.len());
-    out.extend_from_slice(new_line.as_bytes());
-    out.extend_from_slice(&request_bytes[line_end..]);
+/// 为动态压缩改写上游响应头：去掉原有的长度与传输编码，ETag 改为弱校验器，
+/// Vary 中加入 Accept-Encoding，再追加 Content-Encoding 与 chunked
+fn rewrite_for_compression(header: &[u8], encoding: &str) -> Vec<u8> {
+    let mut out = Vec::with_capacity(header.len() + 96);
+    let mut has_vary = false;
+    for line in head_lines(header) {
+        match split_field(line) {
+            Some((key, _)) if key.eq_ignore_ascii_case("content-length") || key.eq_ignore_ascii_case("transfer-encoding") => {
+                continue;
+            }
+            Some((key, value)) if key.eq_ignore_ascii_case("etag") && !value.trim_ascii().starts_with(b"W/") => {
+                out.extend_from_slice(format!("{}: W/", key).as_bytes());
+                out.extend_from_slice(value.trim_ascii());
+            }
+            Some((key, value)) if key.eq_ignore_ascii_case("vary") => {
+                has_vary = true;
+                let listed = value.split(|&b| b == b',').any(|v| {
+                    let v = v.trim_ascii();
+                    v == b"*" || v.eq_ignore_ascii_case(b"accept-encoding")
+                });
+                out.extend_from_slice(line);
+                if !listed {
+                    out.extend_from_slice(b", Accept-Encoding");
+                }
+            }
+            _ => out.extend_from_slice(line),
+        }
+        out.extend_from_slice(b"\r\n");
+    }
+    if !has_vary {
+        out.extend_from_slice(b"Vary: Accept-Encoding\r\n");
+    }
+    out.extend_from_slice(format!("Content-Encoding: {}\r\nTransfer-Encoding: chunked\r\n\r\n", encoding).as_bytes());
     out
 }
 
-fn find_header_end(buf: &[u8]) -> Option<usize> {
-    buf.windows(4).position(|w| w == b"\r\n\r\n").map(|i| i + 4)
+/// 逐行遍历已校验过的头部（状态行在内），跳过结尾空行；字段值可能含 obs-text，按字节处理
+fn head_lines(header: &[u8]) -> impl Iterator<Item = &[u8]> {
+    header
+        .split(|&b| b == b'\n')
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+        .filter(|line| !line.is_empty())
 }
 
-/// 从请求头解析 Content-Length（若存在）
-fn request_content_length(request_bytes: &[u8]) -> Option<(usize, usize)> {
-    let header_end = find_header_end(request_bytes)?;
-    let header_str = String::from_utf8_lossy(&request_bytes[..header_end]);
-    for line in header_str.lines() {
-        if let Some((key, value)) = line.split_once(':') {
-            if key.trim().eq_ignore_ascii_case("content-length") {
-                if let Ok(len) = value.trim().parse::<usize>() {
-                    return Some((header_end, len));
-                }
-            }
+/// 按 Content-Length 把请求体转发给上游：先用缓冲中的前缀，不足再从客户端读取，
+/// 多读到的字节留在缓冲中属于下一个请求
+async fn forward_body(
+    client: &mut Client,
+    upstream: &mut TimeoutStream<TcpStream>,
+    length: usize,
+) -> Result<(), BodyError> {
+    let mut remaining = length;
+    loop {
+        let n = remaining.min(client.buffer.len());
+        upstream.write_all(&client.buffer[..n]).await.map_err(BodyError::Upstream)?;
+        client.buffer.drain(..n);
+        remaining -= n;
+        if remaining == 0 {
+            return Ok(());
+        }
+        if read_more(&mut client.stream, &mut client.buffer).await? == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "client closed inside request body",
+            )
+            .into());
         }
     }
-    None
+}
+
+/// 边解码边转发 chunked 请求体，按解码后的数据段重新编码为 chunk（trailer 不转发）
+async fn forward_chunked_body(
+    client: &mut Client,
+    upstream: &mut TimeoutStream<TcpStream>,
+    limit: u64,
+) -> Result<(), BodyError> {
+    let mut chunked = ChunkedBody::new(limit);
+    while let Some(data) = chunked.next(&mut client.stream, &mut client.buffer).await? {
+        let mut chunk = Vec::with_capacity(data.len() + 16);
+        chunk.extend_from_slice(format!("{:x}\r\n", data.len()).as_bytes());
+        chunk.extend_from_slice(&data);
+        chunk.extend_from_slice(b"\r\n");
+        upstream.write_all(&chunk).await.map_err(BodyError::Upstream)?;
+    }
+    upstream.write_all(b"0\r\n\r\n").await.map_err(BodyError::Upstream)?;
+    Ok(())
+}
+
+/// 生成转发给上游的请求目标：规范化路径去掉路由前缀后重新编码，原始查询串原样保留
+fn upstream_target(path: &str, route: &str, query: Option<&str>) -> String {
+    let rest = path.strip_prefix(route).unwrap_or(path);
+    let mut target = if rest.starts_with('/') {
+        percent_encode_path(rest)
+    } else {
+        format!("/{}", percent_encode_path(rest))
+    };
+    if let Some(query) = query {
+        target.push('?');
+        target.push_str(query);
+    }
+    target
+}
+
+/// 追加 X-Forwarded-For：已有该头则在末尾追加客户端 IP，否则新增
+fn add_forwarded_for(headers: &mut Vec<(String, Vec<u8>)>, client_ip: IpAddr) {
+    match headers
+        .iter_mut()
+        .find(|(key, _)| key.eq_ignore_ascii_case("x-forwarded-for"))
+    {
+        Some((_, value)) => value.extend_from_slice(format!(", {}", client_ip).as_bytes()),
+        None => headers.push(("X-Forwarded-For".to_string(), client_ip.to_string().into_bytes())),
+    }
 }
 
 /// 读取上游响应头，返回 header 与已读到的 body 前缀
-async fn read_response_head(stream: &mut TcpStream) -> Result<ResponseHead, std::io::Error> {
-    let mut buffer = Vec::with_capacity(4096);
+///
+/// pending 为上一个 1xx 响应之后已读到的数据。不符合 RFC 9112 的响应头返回 InvalidData。
+async fn read_response_head(stream: &mut TimeoutStream<TcpStream>, pending: Vec<u8>) -> Result<ResponseHead, std::io::Error> {
+    let mut buffer = pending;
     let mut temp = [0u8; 4096];
 
     loop {
-        let n = stream.read(&mut temp).await?;
-        if n == 0 {
-            return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "upstream closed"));
-        }
-        buffer.extend_from_slice(&temp[..n]);
-        if buffer.len() > 32768 {
-            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "header too large"));
-        }
-        if let Some(end) = find_header_end(&buffer) {
+        if let Some(end) = find_head_end(&buffer, 0) {
             let header = buffer[..end].to_vec();
             let body_prefix = buffer[end..].to_vec();
-            let info = parse_response_info(&header);
+            let info = parse_response_info(&header)
+                .map_err(|msg| std::io::Error::new(std::io::ErrorKind::InvalidData, msg))?;
             return Ok(ResponseHead {
                 header,
                 body_prefix,
                 info,
             });
         }
+        let n = stream.read(&mut temp).await?;
+        if n == 0 {
+            return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "upstream closed"));
+        }
+        buffer.extend_from_slice(&temp[..n]);
+        if buffer.len() > 32768 {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "header too large"));
+        }
     }
 }
 
-/// 解析响应头中的连接复用与正文长度信息
-fn parse_response_info(header: &[u8]) -> ResponseInfo {
-    let header_str = String::from_utf8_lossy(header);
-    let mut lines = header_str.lines();
-    let status_line = lines.next().unwrap_or("");
-    let is_http10 = status_line.starts_with("HTTP/1.0");
-    let mut connection: Option<String> = None;
-    let mut content_length: Option<usize> = None;
-    let mut chunked = false;
-
-    for line in lines {
-        if let Some((key, value)) = line.split_once(':') {
-            let key = key.trim().to_ascii_lowercase();
-            let value = value.trim().to_ascii_lowercase();
-            match key.as_str() {
-                "connection" => connection = Some(value),
-                "content-length" => content_length = value.parse::<usize>().ok(),
-                "transfer-encoding" => {
-                    if value.contains("chunked") {
-                        chunked = true;
-                    }
-                }
-                _ => {}
-            }
-        }
+/// 严格解析响应头，得到连接复用与正文长度信息
+fn parse_response_info(header: &[u8]) -> Result<ResponseInfo, &'static str> {
+    let lines = split_head_lines(header)?;
+    let (status_line, fields) = lines.split_first().ok_or("empty response header")?;
+
+    // 状态行形如 "HTTP/1.1 200 OK"，原因短语可以含 obs-text，不参与解析
+    let is_http10 = match status_line.get(..9) {
+        Some(b"HTTP/1.1 ") => false,
+        Some(b"HTTP/1.0 ") => true,
+        _ => return Err("unsupported response version"),
+    };
+    let code = &status_line[9..];
+    let code = &code[..code.iter().position(|&b| b == b' ').unwrap_or(code.len())];
+    if code.len() != 3 || !code.iter().all(u8::is_ascii_digit) {
+        return Err("invalid status code");
     }
+    let status = code.iter().fold(0u16, |status, &b| status * 10 + u16::from(b - b'0'));
+
+    let mut headers = parse_fields(fields)?;
+    let framing = check_framing(&mut headers)?;
+    let field = |name: &str| {
+        headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_slice())
+    };
+    let text = |value: &[u8]| String::from_utf8_lossy(value).to_ascii_lowercase();
+    let connection = field("connection").map(text);
+    let content_type = field("content-type").and_then(|value| std::str::from_utf8(value).ok()).map(str::to_string);
+    let encoded = field("content-encoding").is_some_and(|value| !value.eq_ignore_ascii_case(b"identity"));
+    // Cache-Control 可以出现多次，每个值又是逗号分隔的指令列表，指令名不区分大小写
+    let no_transform = headers
+        .iter()
+        .filter(|(key, _)| key.eq_ignore_ascii_case("cache-control"))
+        .flat_map(|(_, value)| value.split(|&b| b == b','))
+        .any(|directive| directive.trim_ascii().eq_ignore_ascii_case(b"no-transform"));
 
     let keep_alive = if is_http10 {
         connection.as_deref().map(|v| v.contains("keep-alive")).unwrap_or(false)
@@ -307,17 +1033,28 @@ fn parse_response_info(header: &[u8]) -> ResponseInfo {
         !connection.as_deref().map(|v| v.contains("close")).unwrap_or(false)
     };
 
-    ResponseInfo {
+    // 最终编码不是 chunked 时响应体读到连接关闭为止
+    Ok(ResponseInfo {
+        status,
         keep_alive,
-        content_length,
-        chunked,
-    }
+        content_length: framing.content_length.map(|length| length as usize),
+        chunked: framing.chunked,
+        content_type,
+        encoded,
+        no_transform,
+    })
+}
+
+/// 检查客户端绑定的上游连接是否仍然空闲可用：不等待，已读到 EOF、错误或多余数据都不能复用
+async fn upstream_idle(stream: &TcpStream) -> bool {
+    let mut buf = [0u8; 1];
+    time::timeout(Duration::ZERO, stream.peek(&mut buf)).await.is_err()
 }
 
 /// 按 Content-Length 转发剩余响应体
 async fn relay_content_length(
-    upstream: &mut TcpStream,
-    client: &mut TcpStream,
+    upstream: &mut TimeoutStream<TcpStream>,
+    client: &mut TimeoutStream<ClientStream>,
     content_length: usize,
     already_sent: usize,
 ) -> Result<(), std::io::Error> {
@@ -326,7 +1063,7 @@ async fn relay_content_length(
     while remaining > 0 {
         let n = upstream.read(&mut temp).await?;
         if n == 0 {
-            break;
+            return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "upstream closed inside response body"));
         }
         let to_write = n.min(remaining);
         client.write_all(&temp[..to_write]).await?;
@@ -335,8 +1072,46 @@ async fn relay_content_length(
     Ok(())
 }
 
+/// 读取未编码的上游响应体（按 chunked、Content-Length 或读到 EOF），压缩后以 chunked 转发
+async fn relay_compressed(
+    upstream: &mut TimeoutStream<TcpStream>,
+    client: &mut TimeoutStream<ClientStream>,
+    info: &ResponseInfo,
+    mut buffer: Vec<u8>,
+    mut body: CompressedBody,
+) -> Result<(), std::io::Error> {
+    if info.chunked {
+        let mut chunked = ChunkedBody::new(0);
+        loop {
+            let data = chunked.next(upstream, &mut buffer).await.map_err(upstream_body_error)?;
+            let Some(data) = data else { break };
+            body.write(client, &data).await?;
+        }
+    } else {
+        let mut remaining = info.content_length.unwrap_or(usize::MAX);
+        let n = buffer.len().min(remaining);
+        body.write(client, &buffer[..n]).await?;
+        remaining -= n;
+        let mut temp = [0u8; 4096];
+        while remaining > 0 {
+            let n = upstream.read(&mut temp).await?;
+            if n == 0 {
+                // 只有按 EOF 界定的响应体才能在这里正常结束
+                if info.content_length.is_some() {
+                    return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "upstream closed inside response body"));
+                }
+                break;
+            }
+            let n = n.min(remaining);
+            body.write(client, &temp[..n]).await?;
+            remaining -= n;
+        }
+    }
+    body.finish(client).await
+}
+
 /// 无明确长度时，读取至 EOF
-async fn relay_until_eof(upstream: &mut TcpStream, client: &mut TcpStream) -> Result<(), std::io::Error> {
+async fn relay_until_eof(upstream: &mut TimeoutStream<TcpStream>, client: &mut TimeoutStream<ClientStream>) -> Result<(), std::io::Error> {
     let mut temp = [0u8; 4096];
     loop {
         let n = upstream.read(&mut temp).await?;
@@ -348,79 +1123,27 @@ async fn relay_until_eof(upstream: &mut TcpStream, client: &mut TcpStream) -> Re
     Ok(())
 }
 
-/// 转发 chunked 响应体，直至遇到 0 长度块
+/// 逐块解码上游的 chunked 响应体，只把校验通过的数据重新编码后转发（trailer 不转发）
 async fn relay_chunked(
-    upstream: &mut TcpStream, // 上游连接
-    client: &mut TcpStream, // 客户端连接
-    mut buffer: Vec<u8>, // 已读取的响应体前缀缓冲
-) -> Result<(), std::io::Error> { // 返回转发结果
-    if !buffer.is_empty() { // 若已有缓存，先转发
-        client.write_all(&buffer).await?; // 发送缓存数据
-    } // 缓存发送完毕
-
-    let mut parse_pos = 0usize; // 当前解析位置
-    let mut temp = [0u8; 4096]; // 读取临时缓冲
-
-    loop { // 循环解析每个 chunk
-        let line_end = match buffer[parse_pos..] // 从当前位置查找行结束
-            .windows(2) // 以 \r\n 为分隔
-            .position(|w| w == b"\r\n") // 找到行结束位置
-        { // 匹配结果分支
-            Some(pos) => parse_pos + pos, // 计算行结束的绝对位置
-            None => { // 缓冲不足，需要继续读取
-                let n = upstream.read(&mut temp).await?; // 从上游读取更多数据
-                if n == 0 { // 上游提前关闭
-                    return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "chunked eof")); // 返回 EOF 错误
-                } // 上游未关闭
-                buffer.extend_from_slice(&temp[..n]); // 扩展缓冲区
-                client.write_all(&temp[..n]).await?; // 同步转发读到的数据
-                continue; // 继续尝试解析
-            } // 缓冲不足分支结束
-        }; // 得到行结束位置
-
-        let size_line = &buffer[parse_pos..line_end]; // 获取 chunk size 行
-        let size = parse_chunk_size(size_line)?; // 解析 chunk 长度
-        let after_line = line_end + 2; // 跳过 \r\n 的起始位置
-
-        if size == 0 { // 遇到最后一个 chunk
-            // 读取可能存在的 trailer 并结束
-            loop { // 继续读取直到 trailer 结束
-                if let Some(pos) = buffer[after_line..] // 在剩余缓冲中找 trailer 结束
-                    .windows(4) // 查找 \r\n\r\n
-                    .position(|w| w == b"\r\n\r\n") // 定位 trailer 结束
-                { // trailer 结束分支
-                    let _ = after_line + pos + 4; // 计算结束位置（仅用于保证逻辑完整）
-                    return Ok(()); // 完成 chunked 转发
-                } // trailer 未结束
-                let n = upstream.read(&mut temp).await?; // 继续从上游读取
-                if n == 0 { // 上游提前关闭
-                    return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "chunked eof")); // 返回 EOF 错误
-                } // 上游未关闭
-                buffer.extend_from_slice(&temp[..n]); // 扩展缓冲区
-                client.write_all(&temp[..n]).await?; // 同步转发读到的数据
-            } // trailer 读取循环结束
-        } // size == 0 分支结束
-
-        let needed = after_line + size + 2; // 该 chunk 包含 size + \r\n 的完整长度
-        while buffer.len() < needed { // 缓冲区不够则继续读取
-            let n = upstream.read(&mut temp).await?; // 从上游读取更多数据
-            if n == 0 { // 上游提前关闭
-                return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "chunked eof")); // 返回 EOF 错误
-            } // 上游未关闭
-            buffer.extend_from_slice(&temp[..n]); // 扩展缓冲区
-            client.write_all(&temp[..n]).await?; // 同步转发读到的数据
-        } // 已达到完整 chunk 长度
-        parse_pos = needed; // 移动到下一个 chunk 的起点
-    } // 继续处理下一个 chunk
-} // relay_chunked 结束
-
-/// 解析 chunk size 的十六进制长度
-fn parse_chunk_size(line: &[u8]) -> Result<usize, std::io::Error> {
-    let mut end = line.len(); // 默认读取到行尾
-    if let Some(pos) = line.iter().position(|b| *b == b';') { // 遇到分号说明有扩展字段
-        end = pos; // 截断到分号前
-    } // 分号处理结束
-    let size_str = String::from_utf8_lossy(&line[..end]); // 解析十六进制字符串
-    usize::from_str_radix(size_str.trim(), 16) // 转换为数字
-        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidData, "invalid chunk size")) // 映射解析错误
-} // parse_chunk_size 结束
+    upstream: &mut TimeoutStream<TcpStream>,
+    client: &mut TimeoutStream<ClientStream>,
+    mut buffer: Vec<u8>,
+) -> Result<(), std::io::Error> {
+    let mut chunked = ChunkedBody::new(0);
+    while let Some(data) = chunked.next(upstream, &mut buffer).await.map_err(upstream_body_error)? {
+        let mut chunk = Vec::with_capacity(data.len() + 16);
+        chunk.extend_from_slice(format!("{:x}\r\n", data.len()).as_bytes());
+        chunk.extend_from_slice(&data);
+        chunk.extend_from_slice(b"\r\n");
+        client.write_all(&chunk).await?;
+    }
+    client.write_all(b"0\r\n\r\n").await
+}
+
+/// 解码上游响应体出错时转成 io::Error，格式错误记为 InvalidData
+fn upstream_body_error(e: BodyError) -> std::io::Error {
+    match e {
+        BodyError::Io(e) => e,
+        e => std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string()),
+    }
+}
@@ -0,0 +1,784 @@
+use std::fmt;
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::config::HeaderBuffersConfig;
+
+/// HTTP 协议版本
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Version {
+    Http10,
+    Http11,
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Version::Http10 => write!(f, "HTTP/1.0"),
+            Version::Http11 => write!(f, "HTTP/1.1"),
+        }
+    }
+}
+
+/// 解析后的请求头部：请求行与按原顺序保存的头字段
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: String,
+    pub target: String,
+    pub version: Version,
+    /// 头字段（保留原始大小写与顺序）；值按原始字节保存，转发时不做改动
+    pub headers: Vec<(String, Vec<u8>)>,
+    /// 原始请求头（含请求行）的字节数
+    pub head_len: usize,
+}
+
+impl Request {
+    /// 取第一个同名头字段的值（名称不区分大小写）；值含 obs-text 等非 UTF-8 字节时返回 None
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .and_then(|(_, value)| std::str::from_utf8(value).ok())
+    }
+
+    /// 请求目标中的路径部分（不含查询串）
+    pub fn path(&self) -> &str {
+        self.target.split_once('?').map_or(self.target.as_str(), |(path, _)| path)
+    }
+
+    /// 查询串（不含 "?"），没有时为 None
+    pub fn query(&self) -> Option<&str> {
+        self.target.split_once('?').map(|(_, query)| query)
+    }
+
+    /// 解析 Content-Length
+    pub fn content_length(&self) -> Option<usize> {
+        self.header("content-length")?.parse().ok()
+    }
+
+    /// 是否使用 chunked 传输编码
+    pub fn is_chunked(&self) -> bool {
+        self.header("transfer-encoding")
+            .is_some_and(|value| value.trim_end().to_ascii_lowercase().ends_with("chunked"))
+    }
+
+    /// 客户端是否希望保持连接：HTTP/1.1 默认保持，HTTP/1.0 需显式 keep-alive
+    pub fn wants_keep_alive(&self) -> bool {
+        let connection = self.header("connection").map(|v| v.to_ascii_lowercase());
+        let has_token = |token: &str| {
+            connection
+                .as_deref()
+                .is_some_and(|v| v.split(',').any(|t| t.trim() == token))
+        };
+        match self.version {
+            Version::Http11 => !has_token("close"),
+            Version::Http10 => has_token("keep-alive"),
+        }
+    }
+
+    /// 请求行，用于日志输出
+    pub fn request_line(&self) -> String {
+        format!("{} {} {}", self.method, self.target, self.version)
+    }
+
+    /// 序列化请求头部
+    pub fn encode_head(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(256);
+        out.extend_from_slice(format!("{}\r\n", self.request_line()).as_bytes());
+        for (key, value) in &self.headers {
+            out.extend_from_slice(key.as_bytes());
+            out.extend_from_slice(b": ");
+            out.extend_from_slice(value);
+            out.extend_from_slice(b"\r\n");
+        }
+        out.extend_from_slice(b"\r\n");
+        out
+    }
+}
+
+/// 读取或解析请求头失败的原因
+#[derive(Debug)]
+pub enum RequestError {
+    /// 报文格式错误
+    BadRequest(&'static str),
+    /// 请求行超过单个缓冲区大小
+    UriTooLong,
+    /// 头字段行或头部总量超过缓冲区限制
+    HeaderTooLarge,
+    /// 头部未读完连接就关闭
+    UnexpectedEof,
+    Io(std::io::Error),
+}
+
+impl RequestError {
+    /// 对应的响应状态码；连接层错误返回 None，不再回写响应
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            RequestError::BadRequest(_) => Some(400),
+            RequestError::HeaderTooLarge => Some(431),
+            RequestError::UriTooLong => Some(414),
+            RequestError::UnexpectedEof | RequestError::Io(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for RequestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RequestError::BadRequest(reason) => write!(f, "bad request: {}", reason),
+            RequestError::UriTooLong => write!(f, "request line too long"),
+            RequestError::HeaderTooLarge => write!(f, "request header too large"),
+            RequestError::UnexpectedEof => write!(f, "connection closed inside request header"),
+            RequestError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// 从连接增量读取一个完整的请求头
+///
+/// buffer 为连接级缓冲：调用前可能已有数据，返回后只保留头部之后已读到的字节（请求体前缀）。
+/// 连接在读到任何数据前关闭时返回 Ok(None)。
+pub async fn read_request<S>(
+    stream: &mut S,
+    buffer: &mut Vec<u8>,
+    limits: &HeaderBuffersConfig,
+) -> Result<Option<Request>, RequestError>
+where
+    S: AsyncRead + Unpin,
+{
+    let max_total = limits.number.saturating_mul(limits.size);
+    let mut scanned = 0usize;
+    let mut temp = vec![0u8; limits.size.clamp(1024, 16384)];
+
+    loop {
+        // 请求行前允许出现空行，直接丢弃
+        let leading = buffer.iter().take_while(|b| **b == b'\r' || **b == b'\n').count();
+        if leading > 0 {
+            buffer.drain(..leading);
+            scanned = 0;
+        }
+
+        if let Some(end) = find_head_end(buffer, scanned) {
+            let request = parse_head(&buffer[..end], limits)?;
+            buffer.drain(..end);
+            return Ok(Some(request));
+        }
+        check_partial_head(buffer, limits, max_total)?;
+        // 结束标记最多跨 3 个字节，下次从这里继续查找
+        scanned = buffer.len().saturating_sub(3);
+
+        let n = stream.read(&mut temp).await.map_err(RequestError::Io)?;
+        if n == 0 {
+            return if buffer.is_empty() {
+                Ok(None)
+            } else {
+                Err(RequestError::UnexpectedEof)
+            };
+        }
+        buffer.extend_from_slice(&temp[..n]);
+    }
+}
+
+/// 从连接再读一批数据追加到缓冲，返回读到的字节数（0 表示对端关闭）
+pub async fn read_more<S>(stream: &mut S, buffer: &mut Vec<u8>) -> Result<usize, std::io::Error>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut temp = [0u8; 8192];
+    let n = stream.read(&mut temp).await?;
+    buffer.extend_from_slice(&temp[..n]);
+    Ok(n)
+}
+
+/// 读取并丢弃指定长度的请求体，多读到的字节留在缓冲中供下一个请求使用
+pub async fn discard_body<S>(
+    stream: &mut S,
+    buffer: &mut Vec<u8>,
+    length: usize,
+) -> Result<(), std::io::Error>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut remaining = length;
+    loop {
+        let n = remaining.min(buffer.len());
+        buffer.drain(..n);
+        remaining -= n;
+        if remaining == 0 {
+            return Ok(());
+        }
+        if read_more(stream, buffer).await? == 0 {
+            return Err(eof_in_body());
+        }
+    }
+}
+
+/// 读取请求体失败的原因
+#[derive(Debug)]
+pub enum BodyError {
+    /// 超过 client_max_body_size
+    TooLarge,
+    /// chunked 编码格式错误
+    Malformed(&'static str),
+    Io(std::io::Error),
+    /// 把请求体写给上游时出错
+    Upstream(std::io::Error),
+}
+
+impl BodyError {
+    /// 对应的响应状态码；客户端读取超时返回 408，其它客户端连接错误返回 None，不再回写响应；
+    /// 上游超时返回 504，其它上游错误返回 502
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            BodyError::TooLarge => Some(413),
+            BodyError::Malformed(_) => Some(400),
+            BodyError::Io(e) if e.kind() == std::io::ErrorKind::TimedOut => Some(408),
+            BodyError::Io(_) => None,
+            BodyError::Upstream(e) if e.kind() == std::io::ErrorKind::TimedOut => Some(504),
+            BodyError::Upstream(_) => Some(502),
+        }
+    }
+}
+
+impl fmt::Display for BodyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BodyError::TooLarge => write!(f, "request body too large"),
+            BodyError::Malformed(reason) => write!(f, "malformed chunked body: {}", reason),
+            BodyError::Io(e) => write!(f, "{}", e),
+            BodyError::Upstream(e) => write!(f, "upstream write failed: {}", e),
+        }
+    }
+}
+
+impl From<std::io::Error> for BodyError {
+    fn from(e: std::io::Error) -> Self {
+        BodyError::Io(e)
+    }
+}
+
+/// chunk-size 行与 trailer 行的最大长度
+const CHUNK_LINE_MAX: usize = 4096;
+
+/// 增量解码 chunked 请求体，数据来自连接缓冲与客户端连接
+///
+/// 解码结束后缓冲中只剩下一个请求的数据。trailer 字段会被读取并丢弃。
+pub struct ChunkedBody {
+    /// 当前 chunk 剩余的数据字节数
+    remaining: usize,
+    /// 已解码的总字节数
+    total: u64,
+    /// 解码后总大小上限，0 表示不限制
+    limit: u64,
+    done: bool,
+}
+
+impl ChunkedBody {
+    pub fn new(limit: u64) -> Self {
+        Self {
+            remaining: 0,
+            total: 0,
+            limit,
+            done: false,
+        }
+    }
+
+    /// 读取下一段解码后的数据；请求体结束时返回 None
+    pub async fn next<S>(&mut self, stream: &mut S, buffer: &mut Vec<u8>) -> Result<Option<Vec<u8>>, BodyError>
+    where
+        S: AsyncRead + Unpin,
+    {
+        if self.done {
+            return Ok(None);
+        }
+
+        if self.remaining == 0 {
+            let line = read_body_line(stream, buffer).await?;
+            let size = parse_chunk_size_line(&line)?;
+            if size == 0 {
+                // 读到空行为止，丢弃 trailer
+                while !read_body_line(stream, buffer).await?.is_empty() {}
+                self.done = true;
+                return Ok(None);
+            }
+            self.total = self.total.saturating_add(size as u64);
+            if self.limit > 0 && self.total > self.limit {
+                return Err(BodyError::TooLarge);
+            }
+            self.remaining = size;
+        }
+
+        if buffer.is_empty() && read_more(stream, buffer).await? == 0 {
+            return Err(eof_in_body().into());
+        }
+        let n = self.remaining.min(buffer.len());
+        let data: Vec<u8> = buffer.drain(..n).collect();
+        self.remaining -= n;
+
+        // chunk 数据之后必须紧跟 CRLF
+        if self.remaining == 0 && !read_body_line(stream, buffer).await?.is_empty() {
+            return Err(BodyError::Malformed("missing CRLF after chunk data"));
+        }
+        Ok(Some(data))
+    }
+}
+
+/// 从缓冲读取一行（不含 CRLF），不足时继续从连接读取
+async fn read_body_line<S>(stream: &mut S, buffer: &mut Vec<u8>) -> Result<Vec<u8>, BodyError>
+where
+    S: AsyncRead + Unpin,
+{
+    loop {
+        if let Some(pos) = buffer.windows(2).position(|w| w == b"\r\n") {
+            let line = buffer[..pos].to_vec();
+            buffer.drain(..pos + 2);
+            return Ok(line);
+        }
+        if buffer.len() > CHUNK_LINE_MAX {
+            return Err(BodyError::Malformed("chunk line too long"));
+        }
+        if read_more(stream, buffer).await? == 0 {
+            return Err(eof_in_body().into());
+        }
+    }
+}
+
+/// 解析 chunk-size 行，忽略 chunk 扩展
+fn parse_chunk_size_line(line: &[u8]) -> Result<usize, BodyError> {
+    let end = line.iter().position(|b| *b == b';').unwrap_or(line.len());
+    let digits = std::str::from_utf8(&line[..end])
+        .map_err(|_| BodyError::Malformed("invalid chunk size"))?
+        .trim_end_matches([' ', '\t']);
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(BodyError::Malformed("invalid chunk size"));
+    }
+    usize::from_str_radix(digits, 16).map_err(|_| BodyError::Malformed("chunk size overflow"))
+}
+
+fn eof_in_body() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "peer closed inside message body")
+}
+
+/// 查找头部结束位置（空行之后）；裸 LF 也视为结束，交由解析阶段拒绝
+pub fn find_head_end(buf: &[u8], from: usize) -> Option<usize> {
+    let mut i = from;
+    while i < buf.len() {
+        if buf[i] == b'\n' {
+            if buf[i + 1..].starts_with(b"\n") {
+                return Some(i + 2);
+            }
+            if buf[i + 1..].starts_with(b"\r\n") {
+                return Some(i + 3);
+            }
+        }
+        i += 1;
+    }
+    None
+}
+
+/// 头部尚未读完时检查已读部分是否已超出缓冲区限制
+fn check_partial_head(
+    buf: &[u8],
+    limits: &HeaderBuffersConfig,
+    max_total: usize,
+) -> Result<(), RequestError> {
+    let first_line = buf.iter().position(|b| *b == b'\n');
+    match first_line {
+        None if buf.len() > limits.size => return Err(RequestError::UriTooLong),
+        None => return Ok(()),
+        Some(_) => {}
+    }
+    let last_line_start = buf.iter().rposition(|b| *b == b'\n').map_or(0, |p| p + 1);
+    if buf.len() - last_line_start > limits.size || buf.len() > max_total {
+        return Err(RequestError::HeaderTooLarge);
+    }
+    Ok(())
+}
+
+/// 解析完整的请求头部
+fn parse_head(head: &[u8], limits: &HeaderBuffersConfig) -> Result<Request, RequestError> {
+    if head.len() > limits.number.saturating_mul(limits.size) {
+        return Err(RequestError::HeaderTooLarge);
+    }
+    let mut lines = split_head_lines(head).map_err(RequestError::BadRequest)?.into_iter();
+
+    let request_line = lines.next().unwrap_or(b"");
+    if request_line.len() > limits.size {
+        return Err(RequestError::UriTooLong);
+    }
+    // 请求行只能由 ASCII 组成，字段值中的 obs-text 留给 parse_fields 处理
+    let request_line =
+        std::str::from_utf8(request_line).map_err(|_| RequestError::BadRequest("non ASCII request line"))?;
+    let mut parts = request_line.split(' ');
+    let (method, target, version) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(m), Some(t), Some(v), None) if !m.is_empty() && !t.is_empty() => (m, t, v),
+        _ => return Err(RequestError::BadRequest("malformed request line")),
+    };
+    if !method.bytes().all(is_token_char) {
+        return Err(RequestError::BadRequest("invalid method"));
+    }
+    if !target.bytes().all(|b| b.is_ascii_graphic()) {
+        return Err(RequestError::BadRequest("invalid request target"));
+    }
+    let version = match version {
+        "HTTP/1.1" => Version::Http11,
+        "HTTP/1.0" => Version::Http10,
+        _ => return Err(RequestError::BadRequest("unsupported HTTP version")),
+    };
+
+    let lines: Vec<&[u8]> = lines.collect();
+    if lines.iter().any(|line| line.len() > limits.size) {
+        return Err(RequestError::HeaderTooLarge);
+    }
+    let mut headers = parse_fields(&lines).map_err(RequestError::BadRequest)?;
+    let framing = check_framing(&mut headers).map_err(RequestError::BadRequest)?;
+    // 请求的最终传输编码必须是 chunked，否则无法确定请求体边界
+    if framing.transfer_encoding && !framing.chunked {
+        return Err(RequestError::BadRequest("unsupported transfer coding"));
+    }
+    if framing.transfer_encoding && version == Version::Http10 {
+        return Err(RequestError::BadRequest("Transfer-Encoding in HTTP/1.0 request"));
+    }
+    let hosts = headers.iter().filter(|(key, _)| key.eq_ignore_ascii_case("host")).count();
+    if hosts > 1 || (hosts == 0 && version == Version::Http11) {
+        return Err(RequestError::BadRequest("missing or duplicate Host header"));
+    }
+
+    Ok(Request {
+        method: method.to_string(),
+        target: target.to_string(),
+        version,
+        headers,
+        head_len: head.len(),
+    })
+}
+
+/// 按 CRLF 切分头部各行，直到空行为止；拒绝裸 LF 与裸 CR
+pub fn split_head_lines(head: &[u8]) -> Result<Vec<&[u8]>, &'static str> {
+    let mut lines = Vec::new();
+    let mut rest = head;
+    while let Some(pos) = rest.windows(2).position(|w| w == b"\r\n") {
+        let line = &rest[..pos];
+        if line.is_empty() {
+            return Ok(lines);
+        }
+        if line.iter().any(|&b| b == b'\r' || b == b'\n') {
+            return Err("bare CR or LF in message head");
+        }
+        lines.push(line);
+        rest = &rest[pos + 2..];
+    }
+    Err("message head not terminated by CRLF")
+}
+
+/// 把头部字段行拆成字段名与原始字段值（不去除空白），字段名必须是 token
+pub fn split_field(line: &[u8]) -> Option<(&str, &[u8])> {
+    let colon = line.iter().position(|&b| b == b':')?;
+    let key = &line[..colon];
+    if key.is_empty() || !key.iter().all(|&b| is_token_char(b)) {
+        return None;
+    }
+    // token 只含 ASCII，转换不会失败
+    Some((std::str::from_utf8(key).ok()?, &line[colon + 1..]))
+}
+
+/// 解析头部字段行；拒绝 obs-fold、非法字段名以及字段值中的控制字符
+///
+/// 字段值允许 obs-text（0x80-0xFF，RFC 9110 第 5.5 节），按原始字节保存。
+pub fn parse_fields(lines: &[&[u8]]) -> Result<Vec<(String, Vec<u8>)>, &'static str> {
+    let mut headers = Vec::with_capacity(lines.len());
+    for line in lines {
+        if line.starts_with(b" ") || line.starts_with(b"\t") {
+            return Err("obsolete line folding");
+        }
+        if !line.contains(&b':') {
+            return Err("header line without colon");
+        }
+        let (key, value) = split_field(line).ok_or("invalid header name")?;
+        if value.iter().any(|&b| (b < 0x20 && b != b'\t') || b == 0x7f) {
+            return Err("invalid header value");
+        }
+        headers.push((key.to_string(), value.trim_ascii().to_vec()));
+    }
+    Ok(headers)
+}
+
+/// 消息体的长度信息（RFC 9112 第 6 节）
+pub struct Framing {
+    pub content_length: Option<u64>,
+    /// 带有 Transfer-Encoding 头部
+    pub transfer_encoding: bool,
+    /// 最终传输编码为 chunked
+    pub chunked: bool,
+}
+
+/// 校验 Content-Length 与 Transfer-Encoding
+///
+/// 两者同时出现、Content-Length 非法或多个值不一致、chunked 重复或不在最后时报错；
+/// 值相同的多个 Content-Length、多个 Transfer-Encoding 各自合并为一个。
+pub fn check_framing(headers: &mut Vec<(String, Vec<u8>)>) -> Result<Framing, &'static str> {
+    let mut content_length: Option<u64> = None;
+    let mut codings: Vec<String> = Vec::new();
+    for (key, value) in headers.iter() {
+        if key.eq_ignore_ascii_case("content-length") {
+            let value = std::str::from_utf8(value).map_err(|_| "invalid Content-Length")?;
+            for item in value.split(',') {
+                let item = item.trim_matches([' ', '\t']);
+                if item.is_empty() || !item.bytes().all(|b| b.is_ascii_digit()) {
+                    return Err("invalid Content-Length");
+                }
+                let length: u64 = item.parse().map_err(|_| "invalid Content-Length")?;
+                if content_length.is_some_and(|previous| previous != length) {
+                    return Err("conflicting Content-Length values");
+                }
+                content_length = Some(length);
+            }
+        } else if key.eq_ignore_ascii_case("transfer-encoding") {
+            let value = std::str::from_utf8(value).map_err(|_| "invalid Transfer-Encoding")?;
+            for coding in value.split(',') {
+                let coding = coding.trim_matches([' ', '\t']);
+                if !coding.is_empty() {
+                    codings.push(coding.to_ascii_lowercase());
+                }
+            }
+        }
+    }
+
+    let transfer_encoding = headers.iter().any(|(key, _)| key.eq_ignore_ascii_case("transfer-encoding"));
+    if transfer_encoding && content_length.is_some() {
+        return Err("both Content-Length and Transfer-Encoding");
+    }
+    if transfer_encoding && codings.is_empty() {
+        return Err("empty Transfer-Encoding");
+    }
+    let chunked_count = codings.iter().filter(|c| c.as_str() == "chunked").count();
+    let chunked = codings.last().is_some_and(|c| c == "chunked");
+    if chunked_count > 1 || (chunked_count == 1 && !chunked) {
+        return Err("chunked must be the final transfer coding");
+    }
+
+    if let Some(length) = content_length {
+        headers.retain(|(key, _)| !key.eq_ignore_ascii_case("content-length"));
+        headers.push(("Content-Length".to_string(), length.to_string().into_bytes()));
+    }
+    if transfer_encoding {
+        // 多个 Transfer-Encoding 头部合并为一个，后续只需查看单个头部
+        headers.retain(|(key, _)| !key.eq_ignore_ascii_case("transfer-encoding"));
+        headers.push(("Transfer-Encoding".to_string(), codings.join(", ").into_bytes()));
+    }
+    Ok(Framing {
+        content_length,
+        transfer_encoding,
+        chunked,
+    })
+}
+
+/// RFC 9110 token 字符
+fn is_token_char(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+/// 状态码对应的原因短语
+pub fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        203 => "Non-Authoritative Information",
+        204 => "No Content",
+        205 => "Reset Content",
+        206 => "Partial Content",
+        300 => "Multiple Choices",
+        301 => "Moved Permanently",
+        302 => "Found",
+        303 => "See Other",
+        304 => "Not Modified",
+        307 => "Temporary Redirect",
+        308 => "Permanent Redirect",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        402 => "Payment Required",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        406 => "Not Acceptable",
+        407 => "Proxy Authentication Required",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        410 => "Gone",
+        411 => "Length Required",
+        412 => "Precondition Failed",
+        413 => "Content Too Large",
+        414 => "URI Too Long",
+        415 => "Unsupported Media Type",
+        416 => "Range Not Satisfiable",
+        417 => "Expectation Failed",
+        421 => "Misdirected Request",
+        422 => "Unprocessable Content",
+        426 => "Upgrade Required",
+        428 => "Precondition Required",
+        429 => "Too Many Requests",
+        431 => "Request Header Fields Too Large",
+        451 => "Unavailable For Legal Reasons",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        505 => "HTTP Version Not Supported",
+        _ => "Unknown",
+    }
+}
+
+/// 构造带简短 HTML 正文的错误响应
+pub fn error_response(status: u16) -> Vec<u8> {
+    error_response_with_headers(status, &[])
+}
+
+/// 构造错误响应，并附加额外的头部（如 405 的 Allow）
+pub fn error_response_with_headers(status: u16, headers: &[(&str, &str)]) -> Vec<u8> {
+    let reason = reason_phrase(status);
+    let body = format!("<h1>{} {}</h1>", status, reason);
+    let extra: String = headers
+        .iter()
+        .map(|(key, value)| format!("{}: {}\r\n", key, value))
+        .collect();
+    format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: text/html\r\nContent-Length: {}\r\n{}Connection: close\r\n\r\n{}",
+        status,
+        reason,
+        body.len(),
+        extra,
+        body
+    )
+    .into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(head: &str) -> Result<Request, RequestError> {
+        parse_head(head.as_bytes(), &HeaderBuffersConfig::default())
+    }
+
+    fn framing(fields: &[(&str, &str)]) -> Result<Framing, &'static str> {
+        let mut headers = fields.iter().map(|(k, v)| (k.to_string(), v.as_bytes().to_vec())).collect();
+        check_framing(&mut headers)
+    }
+
+    #[test]
+    fn accepts_well_formed_request() {
+        let request = parse("POST /a?b=1 HTTP/1.1\r\nHost: x\r\nContent-Length: 5\r\n\r\n").unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path(), "/a");
+        assert_eq!(request.query(), Some("b=1"));
+        assert_eq!(request.content_length(), Some(5));
+    }
+
+    #[test]
+    fn rejects_content_length_with_transfer_encoding() {
+        assert!(framing(&[("Content-Length", "5"), ("Transfer-Encoding", "chunked")]).is_err());
+        assert!(matches!(
+            parse("POST / HTTP/1.1\r\nHost: x\r\nContent-Length: 5\r\nTransfer-Encoding: chunked\r\n\r\n"),
+            Err(RequestError::BadRequest(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_conflicting_content_length() {
+        assert!(framing(&[("Content-Length", "5"), ("Content-Length", "6")]).is_err());
+        assert!(framing(&[("Content-Length", "5, 6")]).is_err());
+        assert!(framing(&[("Content-Length", "+5")]).is_err());
+        assert!(framing(&[("Content-Length", "")]).is_err());
+    }
+
+    #[test]
+    fn merges_identical_content_length() {
+        let mut headers = vec![
+            ("Content-Length".to_string(), b"5".to_vec()),
+            ("content-length".to_string(), b"5, 5".to_vec()),
+        ];
+        let framing = check_framing(&mut headers).unwrap();
+        assert_eq!(framing.content_length, Some(5));
+        assert_eq!(headers, vec![("Content-Length".to_string(), b"5".to_vec())]);
+    }
+
+    #[test]
+    fn chunked_must_be_final_and_unique() {
+        assert!(framing(&[("Transfer-Encoding", "chunked, gzip")]).is_err());
+        assert!(framing(&[("Transfer-Encoding", "chunked"), ("Transfer-Encoding", "chunked")]).is_err());
+        assert!(framing(&[("Transfer-Encoding", "")]).is_err());
+        let merged = framing(&[("Transfer-Encoding", "gzip"), ("Transfer-Encoding", "Chunked")]).unwrap();
+        assert!(merged.chunked);
+        assert!(matches!(
+            parse("POST / HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: gzip\r\n\r\n"),
+            Err(RequestError::BadRequest(_))
+        ));
+        assert!(matches!(
+            parse("POST / HTTP/1.0\r\nTransfer-Encoding: chunked\r\n\r\n"),
+            Err(RequestError::BadRequest(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_obs_fold() {
+        assert!(matches!(
+            parse("GET / HTTP/1.1\r\nHost: x\r\nX-A: 1\r\n  continued\r\n\r\n"),
+            Err(RequestError::BadRequest("obsolete line folding"))
+        ));
+        assert!(matches!(
+            parse("GET / HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: gzip\r\n\tchunked\r\n\r\n"),
+            Err(RequestError::BadRequest(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_bare_lf_and_cr() {
+        assert_eq!(find_head_end(b"GET / HTTP/1.1\nHost: x\n\n", 0), Some(24));
+        assert!(matches!(
+            parse("GET / HTTP/1.1\nHost: x\n\n"),
+            Err(RequestError::BadRequest(_))
+        ));
+        assert!(matches!(
+            parse("GET / HTTP/1.1\r\nHost: x\nX-A: 1\r\n\r\n"),
+            Err(RequestError::BadRequest(_))
+        ));
+        assert!(matches!(
+            parse("GET / HTTP/1.1\r\nHost: x\rX-A: 1\r\n\r\n"),
+            Err(RequestError::BadRequest(_))
+        ));
+    }
+
+    #[test]
+    fn keeps_obs_text_in_field_values() {
+        let head = b"GET / HTTP/1.1\r\nHost: x\r\nCookie: name=caf\xe9\r\n\r\n";
+        let request = parse_head(head, &HeaderBuffersConfig::default()).unwrap();
+        assert_eq!(request.headers[1].1, b"name=caf\xe9".to_vec());
+        assert_eq!(request.header("cookie"), None);
+        assert!(request.encode_head().windows(13).any(|w| w == b"Cookie: name="));
+        assert!(request.encode_head().ends_with(b"caf\xe9\r\n\r\n"));
+        assert!(matches!(
+            parse_head(b"GET / HTTP/1.1\r\nHost: x\r\nX-A: a\x01b\r\n\r\n", &HeaderBuffersConfig::default()),
+            Err(RequestError::BadRequest("invalid header value"))
+        ));
+        assert!(matches!(
+            parse_head(b"GET /caf\xe9 HTTP/1.1\r\nHost: x\r\n\r\n", &HeaderBuffersConfig::default()),
+            Err(RequestError::BadRequest(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_invalid_names_and_hosts() {
+        assert!(matches!(
+            parse("GET / HTTP/1.1\r\nHost : x\r\n\r\n"),
+            Err(RequestError::BadRequest("invalid header name"))
+        ));
+        assert!(matches!(parse("GET / HTTP/1.1\r\n\r\n"), Err(RequestError::BadRequest(_))));
+        assert!(matches!(
+            parse("GET / HTTP/1.1\r\nHost: a\r\nHost: b\r\n\r\n"),
+            Err(RequestError::BadRequest(_))
+        ));
+        assert!(parse("GET / HTTP/1.0\r\n\r\n").is_ok());
+    }
+}
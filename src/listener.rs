@@ -1,18 +1,556 @@
-use tokio::net::{TcpListener, TcpSocket};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::Duration;
 
-/// 创建 TCP 监听器，并按平台设置端口复用
-pub fn create_listener(addr: &str) -> Result<TcpListener, Box<dyn std::error::Error>> {
-    let socket = TcpSocket::new_v4()?;
+use socket2::{Domain, Protocol, SockRef, Socket, TcpKeepalive, Type};
+use tokio::net::TcpListener;
+#[cfg(unix)]
+use tokio::net::UnixListener;
+
+use crate::config::ListenConfig;
+use crate::stream::{ClientStream, PeerAddr};
+
+#[cfg(unix)]
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+#[cfg(unix)]
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+
+/// master 通过该环境变量把继承的监听 fd 告知 worker，格式为每行 "addr=fd"
+pub const INHERITED_FDS_ENV: &str = "MINI_NGINX_LISTEN_FDS";
+
+/// worker 内的监听器：TCP 或 Unix 域套接字
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+impl Listener {
+    /// 接受一个连接，返回统一的客户端流与对端地址
+    pub async fn accept(&self) -> Result<(ClientStream, PeerAddr), std::io::Error> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (stream, addr) = listener.accept().await?;
+                Ok((ClientStream::Tcp(stream), PeerAddr::Tcp(addr)))
+            }
+            #[cfg(unix)]
+            Listener::Unix(listener) => {
+                let (stream, _) = listener.accept().await?;
+                let peer = PeerAddr::from_unix(&stream);
+                Ok((ClientStream::Unix(stream), peer))
+            }
+        }
+    }
+}
+
+/// 打开监听器：优先使用 master 继承下来的 fd，否则自行创建
+pub fn open_listener(
+    listen: &ListenConfig,
+    inherited: &mut HashMap<String, i32>,
+) -> Result<Listener, Box<dyn std::error::Error>> {
+    #[cfg(unix)]
+    if let Some(fd) = inherited.remove(&listen.addr) {
+        return listener_from_fd(listen, fd);
+    }
+    #[cfg(not(unix))]
+    let _ = inherited;
+    create_listener(listen)
+}
+
+/// 创建监听器，并按监听项设置端口复用、IPv6 与 Unix 套接字选项
+pub fn create_listener(listen: &ListenConfig) -> Result<Listener, Box<dyn std::error::Error>> {
+    if listen.unix_path().is_some() {
+        #[cfg(unix)]
+        {
+            let listener = bind_unix_listener(listen)?;
+            listener.set_nonblocking(true)?;
+            return Ok(Listener::Unix(UnixListener::from_std(listener)?));
+        }
+        #[cfg(not(unix))]
+        return Err(format!("{}: unix sockets are not supported on this platform", listen.addr).into());
+    }
+
+    let socket = bind_tcp_socket(listen)?;
+    socket.set_nonblocking(true)?;
+    let listener = TcpListener::from_std(socket.into())?;
+    Ok(Listener::Tcp(listener))
+}
+
+/// 按监听项创建、绑定并开始监听 TCP 套接字
+fn bind_tcp_socket(listen: &ListenConfig) -> Result<Socket, Box<dyn std::error::Error>> {
+    let addr: SocketAddr = listen.addr.parse()?;
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
 
     #[cfg(unix)]
     // Unix 下使用 SO_REUSEPORT 允许多进程监听同一端口
-    socket.set_reuseport(true)?;
+    socket.set_reuse_port(listen.reuseport)?;
     #[cfg(windows)]
     // Windows 下使用 SO_REUSEADDR
-    socket.set_reuseaddr(true)?;
+    socket.set_reuse_address(true)?;
+
+    if addr.is_ipv6() {
+        socket.set_only_v6(listen.ipv6only)?;
+    }
+
+    // 缓冲区需在 listen 前设置，接受的连接才能继承对应的窗口大小
+    apply_buffer_options(&socket, listen)?;
 
     // 绑定地址并开始监听
-    socket.bind(addr.parse()?)?;
-    let listener = socket.listen(1024)?;
-    Ok(listener)
+    socket
+        .bind(&addr.into())
+        .map_err(|e| format!("bind {}: {}", listen.addr, e))?;
+    socket.listen(listen.backlog)?;
+    apply_tcp_listen_options(&socket, listen)?;
+    Ok(socket)
+}
+
+/// 对新接受的 TCP 连接应用 TCP_NODELAY 与 keepalive 设置，失败只记录日志
+pub fn tune_accepted_stream(stream: &ClientStream, listen: &ListenConfig) {
+    let ClientStream::Tcp(stream) = stream else {
+        return;
+    };
+    if listen.tcp_nodelay
+        && let Err(e) = stream.set_nodelay(true)
+    {
+        eprintln!("Listener {}: set TCP_NODELAY failed: {}", listen.addr, e);
+    }
+    if let Some(keepalive) = &listen.so_keepalive {
+        let mut params = TcpKeepalive::new();
+        if let Some(idle) = keepalive.idle_secs {
+            params = params.with_time(Duration::from_secs(idle));
+        }
+        if let Some(interval) = keepalive.interval_secs {
+            params = params.with_interval(Duration::from_secs(interval));
+        }
+        #[cfg(not(windows))]
+        if let Some(count) = keepalive.count {
+            params = params.with_retries(count);
+        }
+        if let Err(e) = SockRef::from(stream).set_tcp_keepalive(&params) {
+            eprintln!("Listener {}: set SO_KEEPALIVE failed: {}", listen.addr, e);
+        }
+    }
+}
+
+/// 设置 SO_RCVBUF / SO_SNDBUF
+fn apply_buffer_options(socket: &Socket, listen: &ListenConfig) -> Result<(), std::io::Error> {
+    if let Some(size) = listen.rcvbuf {
+        socket.set_recv_buffer_size(size)?;
+    }
+    if let Some(size) = listen.sndbuf {
+        socket.set_send_buffer_size(size)?;
+    }
+    Ok(())
+}
+
+/// 设置监听套接字上的 TCP_DEFER_ACCEPT 与 TCP_FASTOPEN
+fn apply_tcp_listen_options(socket: &Socket, listen: &ListenConfig) -> Result<(), std::io::Error> {
+    #[cfg(target_os = "linux")]
+    {
+        if let Some(secs) = listen.defer_accept_secs {
+            set_tcp_int_option(socket, libc::TCP_DEFER_ACCEPT, secs as libc::c_int)?;
+        }
+        if let Some(queue) = listen.fastopen {
+            set_tcp_int_option(socket, libc::TCP_FASTOPEN, queue as libc::c_int)?;
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = socket;
+        if listen.defer_accept_secs.is_some() || listen.fastopen.is_some() {
+            eprintln!("Listener {}: defer_accept_secs/fastopen are only supported on Linux", listen.addr);
+        }
+    }
+    Ok(())
+}
+
+/// 以 int 值设置 IPPROTO_TCP 层的套接字选项
+#[cfg(target_os = "linux")]
+fn set_tcp_int_option(socket: &Socket, name: libc::c_int, value: libc::c_int) -> Result<(), std::io::Error> {
+    // SAFETY: 传入的指针指向栈上的 c_int，长度与之匹配
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::IPPROTO_TCP,
+            name,
+            &value as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// 读取 master 传入的继承 fd 列表
+pub fn inherited_fds() -> HashMap<String, i32> {
+    let mut fds = HashMap::new();
+    if let Ok(value) = std::env::var(INHERITED_FDS_ENV) {
+        for line in value.lines() {
+            if let Some((addr, fd)) = line.rsplit_once('=')
+                && let Ok(fd) = fd.parse::<i32>()
+            {
+                fds.insert(addr.to_string(), fd);
+            }
+        }
+    }
+    fds
+}
+
+/// 把继承的 fd 包装为 tokio 监听器
+#[cfg(unix)]
+fn listener_from_fd(listen: &ListenConfig, fd: RawFd) -> Result<Listener, Box<dyn std::error::Error>> {
+    // SAFETY: fd 由 master 专门为该地址打开并继承给本进程，且只会被取用一次
+    if listen.unix_path().is_some() {
+        let listener = unsafe { std::os::unix::net::UnixListener::from_raw_fd(fd) };
+        listener.set_nonblocking(true)?;
+        Ok(Listener::Unix(UnixListener::from_std(listener)?))
+    } else {
+        let listener = unsafe { std::net::TcpListener::from_raw_fd(fd) };
+        listener.set_nonblocking(true)?;
+        Ok(Listener::Tcp(TcpListener::from_std(listener)?))
+    }
+}
+
+/// master 持有并继承给 worker 的监听套接字
+///
+/// Unix 套接字与关闭了 reuseport 的 TCP 地址不能让多个 worker 各自绑定，
+/// 因此由 master 统一绑定一次，worker 通过 fd 继承共享。
+#[cfg(unix)]
+pub struct SharedListener {
+    pub addr: String,
+    socket: OwnedFd,
+    /// 由 master 创建的套接字文件，释放时一并删除
+    unlink_path: Option<PathBuf>,
+}
+
+#[cfg(unix)]
+impl SharedListener {
+    pub fn raw_fd(&self) -> RawFd {
+        self.socket.as_raw_fd()
+    }
+}
+
+#[cfg(unix)]
+impl Drop for SharedListener {
+    fn drop(&mut self) {
+        if let Some(path) = &self.unlink_path {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// systemd 套接字激活传入的第一个 fd（SD_LISTEN_FDS_START）
+#[cfg(unix)]
+const SD_LISTEN_FDS_START: RawFd = 3;
+
+/// systemd 套接字激活传入的监听套接字，由 master 持有整个生命周期
+#[cfg(unix)]
+pub struct ActivatedSocket {
+    socket: Socket,
+    local: socket2::SockAddr,
+}
+
+#[cfg(unix)]
+impl ActivatedSocket {
+    /// 判断该套接字是否对应某个监听项（按本地地址或套接字路径比较）
+    fn matches(&self, listen: &ListenConfig) -> bool {
+        match listen.unix_path() {
+            Some(path) => self.local.as_pathname() == Some(Path::new(path)),
+            None => listen.addr.parse::<SocketAddr>().ok() == self.local.as_socket(),
+        }
+    }
+}
+
+/// 读取 LISTEN_FDS/LISTEN_PID，接管 systemd 预先打开的监听套接字
+#[cfg(unix)]
+pub fn take_activated_sockets() -> Vec<ActivatedSocket> {
+    let pid = std::env::var("LISTEN_PID").ok().and_then(|v| v.parse::<u32>().ok());
+    // LISTEN_PID 不是本进程说明变量是从父进程遗留的，不能接管
+    if pid != Some(std::process::id()) {
+        return Vec::new();
+    }
+    let count = std::env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|v| v.parse::<RawFd>().ok())
+        .unwrap_or(0);
+
+    let mut sockets = Vec::new();
+    for fd in SD_LISTEN_FDS_START..SD_LISTEN_FDS_START + count {
+        // SAFETY: 按 sd_listen_fds 约定，这些 fd 由 systemd 打开并交给本进程独占
+        let socket = unsafe { Socket::from_raw_fd(fd) };
+        // 原始 fd 只留在 master，worker 继承的是按需复制的副本
+        if let Err(e) = socket.set_cloexec(true) {
+            eprintln!("Listener: systemd fd {}: set FD_CLOEXEC failed: {}", fd, e);
+        }
+        match socket.local_addr() {
+            Ok(local) => sockets.push(ActivatedSocket { socket, local }),
+            Err(e) => eprintln!("Listener: systemd fd {} is not a socket: {}", fd, e),
+        }
+    }
+    sockets
+}
+
+/// 为与 systemd 激活套接字匹配的监听项复制出可继承的 fd
+///
+/// 激活套接字不依赖其它监听项能否打开，单个复制失败只记录日志，
+/// 保证每次拉起 worker 时都能把它们传下去。
+#[cfg(unix)]
+pub fn activated_listeners(listens: &[ListenConfig], activated: &[ActivatedSocket]) -> Vec<SharedListener> {
+    let mut listeners: Vec<SharedListener> = Vec::new();
+    for listen in listens {
+        if listeners.iter().any(|l| l.addr == listen.addr) {
+            continue;
+        }
+        let Some(socket) = activated.iter().find(|s| s.matches(listen)) else {
+            continue;
+        };
+        let listener = socket.socket.try_clone().and_then(|socket| {
+            let listener = SharedListener {
+                addr: listen.addr.clone(),
+                socket: socket.into(),
+                unlink_path: None,
+            };
+            set_inheritable(listener.raw_fd())?;
+            Ok(listener)
+        });
+        match listener {
+            Ok(listener) => listeners.push(listener),
+            Err(e) => eprintln!("Listener {}: failed to share systemd socket: {}", listen.addr, e),
+        }
+    }
+
+    for socket in activated {
+        if !listens.iter().any(|listen| socket.matches(listen)) {
+            eprintln!(
+                "Listener: systemd socket {:?} does not match any configured listen address",
+                socket.local.as_socket().map(|a| a.to_string()).or_else(|| {
+                    socket.local.as_pathname().map(|p| p.display().to_string())
+                })
+            );
+        }
+    }
+
+    listeners
+}
+
+/// 按配置准备 master 需要绑定并持有的套接字，地址未变的沿用旧套接字
+///
+/// 与 systemd 传入套接字匹配的监听项由 activated_listeners 处理，这里跳过；
+/// Unix 套接字与 reuseport 为 false 的 TCP 地址由 master 绑定，
+/// 其余 TCP 地址仍由各 worker 通过 SO_REUSEPORT 自行监听。
+/// 沿用的套接字先复制一份，全部成功后才把套接字文件的删除责任从旧集合转给新集合，
+/// 失败时旧集合保持原样，调用方可以继续使用。
+#[cfg(unix)]
+pub fn prepare_shared_listeners(
+    listens: &[ListenConfig],
+    previous: &mut [SharedListener],
+    activated: &[ActivatedSocket],
+) -> Result<Vec<SharedListener>, Box<dyn std::error::Error>> {
+    let mut shared = Vec::new();
+
+    for listen in listens {
+        if shared.iter().any(|l: &SharedListener| l.addr == listen.addr) {
+            continue;
+        }
+        if activated.iter().any(|s| s.matches(listen)) {
+            continue;
+        }
+        let path = listen.unix_path();
+        if path.is_none() && listen.reuseport {
+            continue;
+        }
+        let listener = match (previous.iter().find(|l| l.addr == listen.addr), path) {
+            (Some(existing), path) => {
+                if let Some(path) = path {
+                    apply_unix_permissions(Path::new(path), listen)?;
+                }
+                SharedListener {
+                    addr: listen.addr.clone(),
+                    socket: existing.socket.try_clone()?,
+                    unlink_path: None,
+                }
+            }
+            (None, Some(path)) => {
+                let socket = bind_unix_listener(listen)?;
+                SharedListener {
+                    addr: listen.addr.clone(),
+                    socket: socket.into(),
+                    unlink_path: Some(PathBuf::from(path)),
+                }
+            }
+            (None, None) => SharedListener {
+                addr: listen.addr.clone(),
+                socket: bind_tcp_socket(listen)?.into(),
+                unlink_path: None,
+            },
+        };
+        set_inheritable(listener.raw_fd())?;
+        shared.push(listener);
+    }
+
+    // 新集合已完整建立，沿用的套接字文件改由新集合负责删除
+    for old in previous.iter_mut() {
+        if let Some(listener) = shared.iter_mut().find(|l| l.addr == old.addr && l.unlink_path.is_none()) {
+            listener.unlink_path = old.unlink_path.take();
+        }
+    }
+
+    Ok(shared)
+}
+
+/// 生成传给 worker 的继承 fd 环境变量值
+#[cfg(unix)]
+pub fn encode_inherited_fds<'a>(listeners: impl IntoIterator<Item = &'a SharedListener>) -> String {
+    listeners
+        .into_iter()
+        .map(|l| format!("{}={}", l.addr, l.raw_fd()))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(not(unix))]
+pub struct SharedListener;
+
+#[cfg(not(unix))]
+pub struct ActivatedSocket;
+
+/// 非 Unix 平台不支持 systemd 套接字激活
+#[cfg(not(unix))]
+pub fn take_activated_sockets() -> Vec<ActivatedSocket> {
+    Vec::new()
+}
+
+/// 非 Unix 平台没有激活套接字可传
+#[cfg(not(unix))]
+pub fn activated_listeners(_listens: &[ListenConfig], _activated: &[ActivatedSocket]) -> Vec<SharedListener> {
+    Vec::new()
+}
+
+/// 非 Unix 平台没有需要 master 统一持有的监听套接字
+#[cfg(not(unix))]
+pub fn prepare_shared_listeners(
+    _listens: &[ListenConfig],
+    _previous: &mut [SharedListener],
+    _activated: &[ActivatedSocket],
+) -> Result<Vec<SharedListener>, Box<dyn std::error::Error>> {
+    Ok(Vec::new())
+}
+
+#[cfg(not(unix))]
+pub fn encode_inherited_fds<'a>(_listeners: impl IntoIterator<Item = &'a SharedListener>) -> String {
+    String::new()
+}
+
+/// 清除 FD_CLOEXEC，使 fd 能在 exec 后被 worker 继承
+#[cfg(unix)]
+fn set_inheritable(fd: RawFd) -> Result<(), std::io::Error> {
+    // SAFETY: fd 为本进程持有的有效描述符，仅修改其 fd 标志
+    unsafe {
+        let flags = libc::fcntl(fd, libc::F_GETFD);
+        if flags < 0 || libc::fcntl(fd, libc::F_SETFD, flags & !libc::FD_CLOEXEC) < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// 绑定 Unix 域套接字：清理残留文件、设置权限与属主
+#[cfg(unix)]
+pub fn bind_unix_listener(
+    listen: &ListenConfig,
+) -> Result<std::os::unix::net::UnixListener, Box<dyn std::error::Error>> {
+    let path = Path::new(listen.unix_path().unwrap_or_default());
+    if listen.remove_stale {
+        remove_stale_socket(path)?;
+    }
+    let socket = Socket::new(Domain::UNIX, Type::STREAM, None)?;
+    apply_buffer_options(&socket, listen)?;
+    socket
+        .bind(&socket2::SockAddr::unix(path)?)
+        .map_err(|e| format!("bind {}: {}", listen.addr, e))?;
+    socket.listen(listen.backlog)?;
+    apply_unix_permissions(path, listen)?;
+    Ok(socket.into())
+}
+
+/// 删除无人监听的残留套接字文件；仍有进程在监听或不是套接字文件时报错
+#[cfg(unix)]
+fn remove_stale_socket(path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    if !metadata.file_type().is_socket() {
+        return Err(format!("{} exists and is not a socket", path.display()).into());
+    }
+    match std::os::unix::net::UnixStream::connect(path) {
+        Ok(_) => Err(format!("{} is in use by another process", path.display()).into()),
+        Err(e) if e.kind() == std::io::ErrorKind::ConnectionRefused => {
+            println!("Listener: removing stale socket {}", path.display());
+            std::fs::remove_file(path)?;
+            Ok(())
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// 按配置设置套接字文件的权限位与属主
+#[cfg(unix)]
+fn apply_unix_permissions(path: &Path, listen: &ListenConfig) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(mode) = &listen.mode {
+        let mode = u32::from_str_radix(mode.trim_start_matches("0o"), 8)
+            .map_err(|_| format!("{}: invalid mode {:?}", listen.addr, mode))?;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+    }
+    if listen.owner.is_some() || listen.group.is_some() {
+        let uid = listen.owner.as_deref().map(resolve_uid).transpose()?;
+        let gid = listen.group.as_deref().map(resolve_gid).transpose()?;
+        std::os::unix::fs::chown(path, uid, gid)?;
+    }
+    Ok(())
+}
+
+/// 解析用户名或数字 uid
+#[cfg(unix)]
+fn resolve_uid(name: &str) -> Result<u32, Box<dyn std::error::Error>> {
+    if let Ok(uid) = name.parse::<u32>() {
+        return Ok(uid);
+    }
+    let c_name = std::ffi::CString::new(name)?;
+    let mut buf = vec![0 as libc::c_char; 16384];
+    // SAFETY: passwd 与 buf 均为本地缓冲，getpwnam_r 只写入其中
+    unsafe {
+        let mut pwd: libc::passwd = std::mem::zeroed();
+        let mut result: *mut libc::passwd = std::ptr::null_mut();
+        libc::getpwnam_r(c_name.as_ptr(), &mut pwd, buf.as_mut_ptr(), buf.len(), &mut result);
+        if result.is_null() {
+            return Err(format!("unknown user {:?}", name).into());
+        }
+        Ok(pwd.pw_uid)
+    }
+}
+
+/// 解析组名或数字 gid
+#[cfg(unix)]
+fn resolve_gid(name: &str) -> Result<u32, Box<dyn std::error::Error>> {
+    if let Ok(gid) = name.parse::<u32>() {
+        return Ok(gid);
+    }
+    let c_name = std::ffi::CString::new(name)?;
+    let mut buf = vec![0 as libc::c_char; 16384];
+    // SAFETY: group 与 buf 均为本地缓冲，getgrnam_r 只写入其中
+    unsafe {
+        let mut grp: libc::group = std::mem::zeroed();
+        let mut result: *mut libc::group = std::ptr::null_mut();
+        libc::getgrnam_r(c_name.as_ptr(), &mut grp, buf.as_mut_ptr(), buf.len(), &mut result);
+        if result.is_null() {
+            return Err(format!("unknown group {:?}", name).into());
+        }
+        Ok(grp.gr_gid)
+    }
 }
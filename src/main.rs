@@ -1,10 +1,23 @@
+mod autoindex;
+mod cidr;
+mod compress;
+mod conditional;
 mod config;
+mod encoding;
 mod handler;
+mod http;
+mod httpdate;
 mod listener;
 mod master;
 mod mime;
+mod path;
 mod worker;
 mod pool;
+mod proxy_protocol;
+mod range;
+mod sendfile;
+mod stream;
+mod try_files;
 
 use std::env;
 
@@ -5,6 +5,12 @@ use tokio::fs;
 use tokio::process::{Child, Command};
 use tokio::time::{self, Duration};
 
+use crate::config::load_config;
+use crate::listener::{
+    INHERITED_FDS_ENV, activated_listeners, encode_inherited_fds, prepare_shared_listeners,
+    take_activated_sockets,
+};
+
 /// master 进程：启动 worker 并监听配置文件变化
 pub async fn run_master_process() -> Result<(), Box<dyn std::error::Error>> {
     // 优先使用可用 CPU 核心数作为 worker 数量
@@ -12,7 +18,17 @@ pub async fn run_master_process() -> Result<(), Box<dyn std::error::Error>> {
     let self_exe = env::current_exe()?.to_string_lossy().to_string();
     let config_path = "config.json";
     let mut last_modified = fs::metadata(config_path).await?.modified()?;
-    let mut workers = spawn_workers(&self_exe, worker_count).await?;
+
+    // systemd 激活的套接字、Unix 套接字与不开 reuseport 的 TCP 套接字由 master 统一持有，再通过 fd 继承交给各 worker
+    let activated = take_activated_sockets();
+    if !activated.is_empty() {
+        println!("Master: Using {} socket(s) from systemd activation", activated.len());
+    }
+    let config = load_config(config_path).await?;
+    let mut activated_fds = activated_listeners(&config.listen, &activated);
+    let mut shared = prepare_shared_listeners(&config.listen, &mut [], &activated)?;
+    let inherited = encode_inherited_fds(activated_fds.iter().chain(&shared));
+    let mut workers = spawn_workers(&self_exe, worker_count, &inherited).await?;
 
     println!("Master: Running. Modify '{}' to trigger reload.", config_path);
 
@@ -21,22 +37,41 @@ pub async fn run_master_process() -> Result<(), Box<dyn std::error::Error>> {
         time::sleep(Duration::from_secs(1)).await;
         match fs::metadata(config_path).await {
             Ok(metadata) => {
-                if let Ok(modified) = metadata.modified() {
-                    if modified > last_modified {
-                        println!("\n[!] Config change detected! Reloading...");
-                        last_modified = modified;
+                if let Ok(modified) = metadata.modified()
+                    && modified > last_modified
+                {
+                    println!("\n[!] Config change detected! Reloading...");
+                    last_modified = modified;
 
-                        // 先杀掉旧 worker，再拉起新 worker
-                        for worker in &mut workers {
-                            worker.kill().await?;
+                    let config = match load_config(config_path).await {
+                        Ok(config) => config,
+                        Err(e) => {
+                            eprintln!("Master: Invalid config, keeping current workers: {}", e);
+                            continue;
                         }
-                        match spawn_workers(&self_exe, worker_count).await {
-                            Ok(new_workers) => {
-                                workers = new_workers;
-                                println!("Master: New workers started successfully!");
-                            }
-                            Err(e) => eprintln!("Master: Failed to spawn workers: {}", e),
+                    };
+
+                    // 先按新配置准备监听套接字，失败则保留旧套接字与旧 worker
+                    let next = match prepare_shared_listeners(&config.listen, &mut shared, &activated) {
+                        Ok(next) => next,
+                        Err(e) => {
+                            eprintln!("Master: Failed to open listeners, keeping current workers: {}", e);
+                            continue;
                         }
+                    };
+                    for worker in &mut workers {
+                        worker.kill().await?;
+                    }
+                    shared = next;
+                    // 激活套接字与 Unix 套接字分开准备，每次拉起 worker 都重新传入
+                    activated_fds = activated_listeners(&config.listen, &activated);
+                    let inherited = encode_inherited_fds(activated_fds.iter().chain(&shared));
+                    match spawn_workers(&self_exe, worker_count, &inherited).await {
+                        Ok(new_workers) => {
+                            workers = new_workers;
+                            println!("Master: New workers started successfully!");
+                        }
+                        Err(e) => eprintln!("Master: Failed to spawn workers: {}", e),
                     }
                 }
             }
@@ -47,19 +82,26 @@ pub async fn run_master_process() -> Result<(), Box<dyn std::error::Error>> {
     }
 }
 
-/// 拉起指定数量的 worker 子进程
+/// 拉起指定数量的 worker 子进程，并传入需要继承的监听 fd
 async fn spawn_workers(
     exec_path: &str,
     count: usize,
+    inherited: &str,
 ) -> Result<Vec<Child>, Box<dyn std::error::Error>> {
     println!("Master [{}] starting {} workers...", std::process::id(), count);
     let mut children = Vec::new();
     for _ in 0..count {
-        let child = Command::new(exec_path)
-            .arg("--worker")
-            .kill_on_drop(true)
-            .spawn()?;
+        let mut command = Command::new(exec_path);
+        command.arg("--worker").kill_on_drop(true);
+        // systemd 的激活变量只对 master 有效，不能泄露给 worker
+        command
+            .env(INHERITED_FDS_ENV, inherited)
+            .env_remove("LISTEN_FDS")
+            .env_remove("LISTEN_PID")
+            .env_remove("LISTEN_FDNAMES");
+        let child = command.spawn()?;
         children.push(child);
     }
     Ok(children)
 }
+
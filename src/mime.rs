@@ -1,9 +1,251 @@
-/// 根据文件后缀返回 Content-Type
-pub fn get_mime_type(filename: &str) -> &str {
-    if filename.ends_with(".html") { "text/html" }
-    else if filename.ends_with(".css") { "text/css" }
-    else if filename.ends_with(".js") { "application/javascript" }
-    else if filename.ends_with(".png") { "image/png" }
-    else if filename.ends_with(".jpg") || filename.ends_with(".jpeg") { "image/jpeg" }
-    else { "application/octet-stream" }
+use std::collections::HashMap;
+
+/// 内置的后缀到 MIME 类型的映射，覆盖 nginx 自带 mime.types 与常见的现代 Web 类型
+const BUILTIN: &[(&str, &str)] = &[
+    // 文本
+    ("html", "text/html"),
+    ("htm", "text/html"),
+    ("shtml", "text/html"),
+    ("css", "text/css"),
+    ("xml", "text/xml"),
+    ("txt", "text/plain"),
+    ("text", "text/plain"),
+    ("log", "text/plain"),
+    ("md", "text/markdown"),
+    ("markdown", "text/markdown"),
+    ("csv", "text/csv"),
+    ("tsv", "text/tab-separated-values"),
+    ("ics", "text/calendar"),
+    ("vtt", "text/vtt"),
+    ("mml", "text/mathml"),
+    ("jad", "text/vnd.sun.j2me.app-descriptor"),
+    ("wml", "text/vnd.wap.wml"),
+    ("htc", "text/x-component"),
+    // 脚本与数据
+    ("js", "application/javascript"),
+    ("mjs", "application/javascript"),
+    ("cjs", "application/javascript"),
+    ("json", "application/json"),
+    ("map", "application/json"),
+    ("jsonld", "application/ld+json"),
+    ("webmanifest", "application/manifest+json"),
+    ("atom", "application/atom+xml"),
+    ("rss", "application/rss+xml"),
+    ("xhtml", "application/xhtml+xml"),
+    ("xspf", "application/xspf+xml"),
+    ("wasm", "application/wasm"),
+    // 图片
+    ("gif", "image/gif"),
+    ("jpeg", "image/jpeg"),
+    ("jpg", "image/jpeg"),
+    ("png", "image/png"),
+    ("apng", "image/apng"),
+    ("webp", "image/webp"),
+    ("avif", "image/avif"),
+    ("heic", "image/heic"),
+    ("heif", "image/heif"),
+    ("jxl", "image/jxl"),
+    ("svg", "image/svg+xml"),
+    ("svgz", "image/svg+xml"),
+    ("ico", "image/x-icon"),
+    ("bmp", "image/x-ms-bmp"),
+    ("tif", "image/tiff"),
+    ("tiff", "image/tiff"),
+    ("wbmp", "image/vnd.wap.wbmp"),
+    ("jng", "image/x-jng"),
+    // 字体
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("ttf", "font/ttf"),
+    ("otf", "font/otf"),
+    ("eot", "application/vnd.ms-fontobject"),
+    // 音频
+    ("mid", "audio/midi"),
+    ("midi", "audio/midi"),
+    ("kar", "audio/midi"),
+    ("mp3", "audio/mpeg"),
+    ("ogg", "audio/ogg"),
+    ("oga", "audio/ogg"),
+    ("opus", "audio/ogg"),
+    ("m4a", "audio/x-m4a"),
+    ("aac", "audio/aac"),
+    ("flac", "audio/flac"),
+    ("wav", "audio/wav"),
+    ("weba", "audio/webm"),
+    ("ra", "audio/x-realaudio"),
+    // 视频
+    ("mp4", "video/mp4"),
+    ("m4v", "video/x-m4v"),
+    ("webm", "video/webm"),
+    ("ogv", "video/ogg"),
+    ("mpeg", "video/mpeg"),
+    ("mpg", "video/mpeg"),
+    ("mov", "video/quicktime"),
+    ("3gp", "video/3gpp"),
+    ("3gpp", "video/3gpp"),
+    ("ts", "video/mp2t"),
+    ("m3u8", "application/vnd.apple.mpegurl"),
+    ("mpd", "application/dash+xml"),
+    ("flv", "video/x-flv"),
+    ("mng", "video/x-mng"),
+    ("asf", "video/x-ms-asf"),
+    ("asx", "video/x-ms-asf"),
+    ("wmv", "video/x-ms-wmv"),
+    ("avi", "video/x-msvideo"),
+    ("mkv", "video/x-matroska"),
+    // 文档
+    ("pdf", "application/pdf"),
+    ("rtf", "application/rtf"),
+    ("doc", "application/msword"),
+    ("xls", "application/vnd.ms-excel"),
+    ("ppt", "application/vnd.ms-powerpoint"),
+    ("docx", "application/vnd.openxmlformats-officedocument.wordprocessingml.document"),
+    ("xlsx", "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet"),
+    ("pptx", "application/vnd.openxmlformats-officedocument.presentationml.presentation"),
+    ("odt", "application/vnd.oasis.opendocument.text"),
+    ("ods", "application/vnd.oasis.opendocument.spreadsheet"),
+    ("odp", "application/vnd.oasis.opendocument.presentation"),
+    ("odg", "application/vnd.oasis.opendocument.graphics"),
+    ("epub", "application/epub+zip"),
+    ("kml", "application/vnd.google-earth.kml+xml"),
+    ("kmz", "application/vnd.google-earth.kmz"),
+    ("ps", "application/postscript"),
+    ("eps", "application/postscript"),
+    ("ai", "application/postscript"),
+    // 压缩包与二进制
+    ("zip", "application/zip"),
+    ("gz", "application/gzip"),
+    ("tgz", "application/gzip"),
+    ("bz2", "application/x-bzip2"),
+    ("xz", "application/x-xz"),
+    ("zst", "application/zstd"),
+    ("br", "application/x-brotli"),
+    ("tar", "application/x-tar"),
+    ("7z", "application/x-7z-compressed"),
+    ("rar", "application/x-rar-compressed"),
+    ("jar", "application/java-archive"),
+    ("war", "application/java-archive"),
+    ("ear", "application/java-archive"),
+    ("apk", "application/vnd.android.package-archive"),
+    ("hqx", "application/mac-binhex40"),
+    ("wmlc", "application/vnd.wap.wmlc"),
+    ("cco", "application/x-cocoa"),
+    ("jardiff", "application/x-java-archive-diff"),
+    ("jnlp", "application/x-java-jnlp-file"),
+    ("run", "application/x-makeself"),
+    ("pl", "application/x-perl"),
+    ("pm", "application/x-perl"),
+    ("prc", "application/x-pilot"),
+    ("pdb", "application/x-pilot"),
+    ("rpm", "application/x-redhat-package-manager"),
+    ("sea", "application/x-sea"),
+    ("swf", "application/x-shockwave-flash"),
+    ("sit", "application/x-stuffit"),
+    ("tcl", "application/x-tcl"),
+    ("tk", "application/x-tcl"),
+    ("der", "application/x-x509-ca-cert"),
+    ("pem", "application/x-x509-ca-cert"),
+    ("crt", "application/x-x509-ca-cert"),
+    ("xpi", "application/x-xpinstall"),
+    ("bin", "application/octet-stream"),
+    ("exe", "application/octet-stream"),
+    ("dll", "application/octet-stream"),
+    ("deb", "application/octet-stream"),
+    ("dmg", "application/octet-stream"),
+    ("iso", "application/octet-stream"),
+    ("img", "application/octet-stream"),
+    ("msi", "application/octet-stream"),
+    ("msp", "application/octet-stream"),
+    ("msm", "application/octet-stream"),
+];
+
+/// 这些非 text/* 类型也是文本，发送时同样追加 charset
+const TEXT_LIKE: &[&str] = &[
+    "application/javascript",
+    "application/json",
+    "application/ld+json",
+    "application/manifest+json",
+    "application/xml",
+    "application/xhtml+xml",
+    "application/atom+xml",
+    "application/rss+xml",
+    "image/svg+xml",
+];
+
+/// 后缀（小写、不含点）到 MIME 类型的映射表
+#[derive(Debug, Clone)]
+pub struct MimeTypes {
+    types: HashMap<String, String>,
+}
+
+impl Default for MimeTypes {
+    fn default() -> Self {
+        Self {
+            types: BUILTIN
+                .iter()
+                .map(|(ext, mime)| (ext.to_string(), mime.to_string()))
+                .collect(),
+        }
+    }
+}
+
+impl MimeTypes {
+    /// 添加或覆盖一个后缀的类型，后缀不区分大小写，可带前导点
+    pub fn insert(&mut self, ext: &str, mime: &str) {
+        let ext = ext.trim_start_matches('.').to_ascii_lowercase();
+        self.types.insert(ext, mime.to_string());
+    }
+
+    /// 合并 nginx 格式的 mime.types 内容，如 "types { text/html html htm; }"
+    pub fn merge_nginx(&mut self, text: &str) -> Result<(), String> {
+        let mut body = String::new();
+        for line in text.lines() {
+            body.push_str(line.split('#').next().unwrap_or(""));
+            body.push('\n');
+        }
+        // 允许省略外层的 types { ... }
+        let body = body.trim();
+        let body = match body.strip_prefix("types") {
+            Some(rest) => rest
+                .trim_start()
+                .strip_prefix('{')
+                .and_then(|rest| rest.trim_end().strip_suffix('}'))
+                .ok_or("mime.types: unbalanced types block")?,
+            None => body,
+        };
+
+        for statement in body.split(';') {
+            let mut tokens = statement.split_whitespace();
+            let Some(mime) = tokens.next() else {
+                continue;
+            };
+            if !mime.contains('/') {
+                return Err(format!("mime.types: invalid type {:?}", mime));
+            }
+            for ext in tokens {
+                self.insert(ext, mime);
+            }
+        }
+        Ok(())
+    }
+
+    /// 根据文件后缀查找 MIME 类型，后缀不区分大小写
+    pub fn lookup(&self, filename: &str) -> Option<&str> {
+        let name = filename.rsplit('/').next().unwrap_or(filename);
+        let (_, ext) = name.rsplit_once('.')?;
+        self.types.get(&ext.to_ascii_lowercase()).map(String::as_str)
+    }
+}
+
+/// 文本类型追加 charset 参数，已带参数或非文本类型原样返回
+pub fn with_charset(mime: &str, charset: Option<&str>) -> String {
+    match charset {
+        Some(charset) if !mime.contains(';') && is_text(mime) => format!("{}; charset={}", mime, charset),
+        _ => mime.to_string(),
+    }
+}
+
+fn is_text(mime: &str) -> bool {
+    let mime = mime.to_ascii_lowercase();
+    mime.starts_with("text/") || TEXT_LIKE.contains(&mime.as_str())
 }
@@ -0,0 +1,164 @@
+use std::path::PathBuf;
+
+use tokio::fs;
+
+use crate::config::SymlinkPolicy;
+
+/// 规范化请求路径：百分号解码、解析 "." 与 ".." 段并合并重复斜杠
+///
+/// 保留结尾斜杠；路径非法（转义错误、非 UTF-8、含 NUL）或越过根目录时返回 None。
+pub fn normalize_path(raw: &str) -> Option<String> {
+    if !raw.starts_with('/') {
+        return None;
+    }
+    let decoded = percent_decode(raw)?;
+    if decoded.contains('\0') {
+        return None;
+    }
+
+    let mut segments: Vec<&str> = Vec::new();
+    for segment in decoded.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop()?;
+            }
+            segment => segments.push(segment),
+        }
+    }
+
+    let mut path = format!("/{}", segments.join("/"));
+    let trailing = decoded.ends_with('/') || decoded.ends_with("/.") || decoded.ends_with("/..");
+    if trailing && !segments.is_empty() {
+        path.push('/');
+    }
+    Some(path)
+}
+
+/// 解码 %XX 转义，结果必须是合法 UTF-8
+pub fn percent_decode(raw: &str) -> Option<String> {
+    let bytes = raw.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = bytes.get(i + 1..i + 3)?;
+            // from_str_radix 会接受 "+f" 这类带符号的写法，先确认两位都是十六进制数字
+            if !hex.iter().all(u8::is_ascii_hexdigit) {
+                return None;
+            }
+            let hex = std::str::from_utf8(hex).ok()?;
+            out.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+/// 把规范化后的路径重新编码为可以放进请求行的形式
+pub fn percent_encode_path(path: &str) -> String {
+    let mut out = String::with_capacity(path.len());
+    for b in path.bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~!$&'()*+,;=:@/".contains(&b) {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{:02X}", b));
+        }
+    }
+    out
+}
+
+/// 按符号链接策略检查 root 之下路径上的每一级
+///
+/// 不存在的路径返回 true，由调用方按 404 处理。
+pub async fn symlinks_allowed(root: &str, path: &str, policy: SymlinkPolicy) -> bool {
+    if policy == SymlinkPolicy::Follow {
+        return true;
+    }
+    let mut current = PathBuf::from(root);
+    for segment in path.split('/').filter(|s| !s.is_empty()) {
+        current.push(segment);
+        let link = match fs::symlink_metadata(&current).await {
+            Ok(meta) => meta,
+            Err(_) => return true,
+        };
+        if !link.file_type().is_symlink() {
+            continue;
+        }
+        if policy == SymlinkPolicy::Deny {
+            return false;
+        }
+        // owner_match：链接与目标属于同一用户时才跟随
+        match fs::metadata(&current).await {
+            Ok(target) if same_owner(&link, &target) => {}
+            Ok(_) => return false,
+            Err(_) => return true,
+        }
+    }
+    true
+}
+
+/// 符号链接与其目标是否属于同一用户
+pub fn same_owner(link: &std::fs::Metadata, target: &std::fs::Metadata) -> bool {
+    owner(link) == owner(target)
+}
+
+#[cfg(unix)]
+fn owner(meta: &std::fs::Metadata) -> Option<u32> {
+    use std::os::unix::fs::MetadataExt;
+    Some(meta.uid())
+}
+
+/// 非 Unix 平台没有文件属主，owner_match 等同于 follow
+#[cfg(not(unix))]
+fn owner(_meta: &std::fs::Metadata) -> Option<u32> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_dot_segments_within_root() {
+        assert_eq!(normalize_path("/a/./b/../c").as_deref(), Some("/a/c"));
+        assert_eq!(normalize_path("/a/b/..").as_deref(), Some("/a/"));
+        assert_eq!(normalize_path("/a/..").as_deref(), Some("/"));
+    }
+
+    #[test]
+    fn rejects_traversal_above_root() {
+        assert_eq!(normalize_path("/.."), None);
+        assert_eq!(normalize_path("/a/../../etc/passwd"), None);
+        assert_eq!(normalize_path("/%2e%2e/etc/passwd"), None);
+        assert_eq!(normalize_path("/a/%2E%2E/%2e%2e/etc"), None);
+        assert_eq!(normalize_path("/a/..%2f..%2fetc"), None);
+    }
+
+    #[test]
+    fn rejects_nul_and_bad_escapes() {
+        assert_eq!(normalize_path("/a%00.html"), None);
+        assert_eq!(normalize_path("/a%2"), None);
+        assert_eq!(normalize_path("/a%zz"), None);
+        assert_eq!(normalize_path("/a%+f"), None);
+        assert_eq!(normalize_path("/%ff%fe"), None);
+        assert_eq!(normalize_path("relative/path"), None);
+    }
+
+    #[test]
+    fn merges_duplicate_slashes_and_keeps_trailing_slash() {
+        assert_eq!(normalize_path("//a///b//").as_deref(), Some("/a/b/"));
+        assert_eq!(normalize_path("/a/.").as_deref(), Some("/a/"));
+        assert_eq!(normalize_path("/").as_deref(), Some("/"));
+    }
+
+    #[test]
+    fn decodes_percent_escapes() {
+        assert_eq!(percent_decode("/my%20file.html").as_deref(), Some("/my file.html"));
+        assert_eq!(percent_decode("/%E4%B8%AD").as_deref(), Some("/中"));
+        assert_eq!(normalize_path("/a%2fb").as_deref(), Some("/a/b"));
+    }
+}
@@ -1,6 +1,7 @@
 use std::collections::{HashMap, VecDeque};
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
+use tokio::io::AsyncWriteExt;
 use tokio::net::TcpStream;
 use tokio::time::timeout;
 
@@ -48,8 +49,16 @@ impl ConnectionPool {
     }
 
     /// 获取可用连接：优先复用池内连接，否则新建
-    pub async fn get(&self, addr: &str) -> Result<TcpStream, std::io::Error> {
-        loop {
+    ///
+    /// 带 PROXY 头部的连接只属于一个客户端，不从池中取，总是新建并先发送该头部。
+    /// 新建连接（含发送 PROXY 头部）超过 connect_timeout 时返回 TimedOut。
+    pub async fn get(
+        &self,
+        addr: &str,
+        proxy_header: Option<&[u8]>,
+        connect_timeout: Duration,
+    ) -> Result<TcpStream, std::io::Error> {
+        while proxy_header.is_none() {
             let entry = {
                 let mut state = self.state.lock().unwrap();
                 let entry = state
@@ -96,7 +105,17 @@ impl ConnectionPool {
 
         // 3. 没拿到，建立新连接
         println!("pool: creating new connection for {}", addr);
-        TcpStream::connect(addr).await
+        let connect = async {
+            let mut stream = TcpStream::connect(addr).await?;
+            if let Some(header) = proxy_header {
+                stream.write_all(header).await?;
+            }
+            Ok(stream)
+        };
+        match timeout(connect_timeout, connect).await {
+            Ok(result) => result,
+            Err(_) => Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "connect timed out")),
+        }
     }
 
     /// 回收连接：把用完的连接放回池子，并触发 LRU 淘汰
@@ -106,7 +125,7 @@ impl ConnectionPool {
         state
             .conns
             .entry(addr.to_string())
-            .or_insert_with(VecDeque::new)
+            .or_default()
             .push_back(PooledConn {
                 stream,
                 last_used: Instant::now(),
@@ -128,11 +147,11 @@ fn evict_oldest(state: &mut PoolState) -> bool {
     let mut oldest_time: Option<Instant> = None;
 
     for (addr, list) in state.conns.iter() {
-        if let Some(front) = list.front() {
-            if oldest_time.map_or(true, |t| front.last_used < t) {
-                oldest_time = Some(front.last_used);
-                oldest_addr = Some(addr.clone());
-            }
+        if let Some(front) = list.front()
+            && oldest_time.is_none_or(|t| front.last_used < t)
+        {
+            oldest_time = Some(front.last_used);
+            oldest_addr = Some(addr.clone());
         }
     }
 
@@ -0,0 +1,345 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::config::{ListenConfig, ProxyProtocolVersion};
+use crate::stream::{ClientStream, PeerAddr};
+
+/// PROXY protocol v2 的 12 字节签名
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+/// PROXY protocol v1 头部的最大长度（含 CRLF）
+const V1_MAX_LEN: usize = 107;
+
+fn invalid(msg: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg.to_string())
+}
+
+/// 在开启 proxy_protocol 的监听器上读取 PROXY 头部，返回应使用的客户端地址
+///
+/// 来源不在信任网段或头部非法时返回 None，调用方应直接关闭连接。
+pub async fn accept_proxy_protocol(
+    stream: &mut ClientStream,
+    peer: PeerAddr,
+    listen: &ListenConfig,
+) -> Option<PeerAddr> {
+    // Unix 套接字只有本机进程能连接，由套接字文件权限控制来源
+    let trusted = match peer.ip() {
+        Some(ip) => listen.proxy_protocol_trusted.iter().any(|cidr| cidr.contains(ip)),
+        None => true,
+    };
+    if !trusted {
+        eprintln!("PROXY protocol: rejecting untrusted source {} on {}", peer, listen.addr);
+        return None;
+    }
+
+    match read_proxy_header(stream).await {
+        Ok(Some(source)) => Some(PeerAddr::Tcp(source)),
+        Ok(None) => Some(peer),
+        Err(e) => {
+            eprintln!("PROXY protocol: invalid header from {} on {}: {}", peer, listen.addr, e);
+            None
+        }
+    }
+}
+
+/// 读取并解析连接开头的 PROXY protocol v1/v2 头部
+///
+/// 只读取头部本身的字节，后续 HTTP 数据保持在连接中。
+/// 返回 Some(源地址)；LOCAL/UNKNOWN 等不携带客户端地址的头部返回 None。
+pub async fn read_proxy_header<S>(stream: &mut S) -> Result<Option<SocketAddr>, std::io::Error>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut prefix = [0u8; 6];
+    stream.read_exact(&mut prefix).await?;
+
+    if &prefix == b"PROXY " {
+        return read_v1(stream).await;
+    }
+    if prefix == V2_SIGNATURE[..6] {
+        let mut rest = [0u8; 10];
+        stream.read_exact(&mut rest).await?;
+        if rest[..6] != V2_SIGNATURE[6..] {
+            return Err(invalid("invalid PROXY v2 signature"));
+        }
+        return read_v2(stream, rest[6], rest[7], u16::from_be_bytes([rest[8], rest[9]])).await;
+    }
+    Err(invalid("missing PROXY protocol header"))
+}
+
+/// 解析 v1 文本头部，例如 "PROXY TCP4 1.2.3.4 5.6.7.8 1234 80\r\n"
+async fn read_v1<S>(stream: &mut S) -> Result<Option<SocketAddr>, std::io::Error>
+where
+    S: AsyncRead + Unpin,
+{
+    // 逐字节读到 CRLF，避免多读走后面的 HTTP 数据
+    let mut line = Vec::with_capacity(V1_MAX_LEN);
+    let mut byte = [0u8; 1];
+    loop {
+        stream.read_exact(&mut byte).await?;
+        line.push(byte[0]);
+        if line.ends_with(b"\r\n") {
+            line.truncate(line.len() - 2);
+            break;
+        }
+        if line.len() + 6 >= V1_MAX_LEN {
+            return Err(invalid("PROXY v1 header too long"));
+        }
+    }
+
+    let line = std::str::from_utf8(&line).map_err(|_| invalid("PROXY v1 header is not ASCII"))?;
+    let parts: Vec<&str> = line.split(' ').collect();
+    match parts.first().copied() {
+        Some("UNKNOWN") => Ok(None),
+        Some(proto @ ("TCP4" | "TCP6")) if parts.len() == 5 => {
+            let ip: IpAddr = parts[1].parse().map_err(|_| invalid("bad PROXY v1 source address"))?;
+            let port: u16 = parts[3].parse().map_err(|_| invalid("bad PROXY v1 source port"))?;
+            if ip.is_ipv4() != (proto == "TCP4") {
+                return Err(invalid("PROXY v1 address family mismatch"));
+            }
+            Ok(Some(SocketAddr::new(ip, port)))
+        }
+        _ => Err(invalid("malformed PROXY v1 header")),
+    }
+}
+
+/// 解析 v2 二进制头部的剩余部分
+async fn read_v2<S>(
+    stream: &mut S,
+    ver_cmd: u8,
+    family: u8,
+    len: u16,
+) -> Result<Option<SocketAddr>, std::io::Error>
+where
+    S: AsyncRead + Unpin,
+{
+    if ver_cmd >> 4 != 2 {
+        return Err(invalid("unsupported PROXY v2 version"));
+    }
+    let mut body = vec![0u8; len as usize];
+    stream.read_exact(&mut body).await?;
+
+    // 命令 0 为 LOCAL（健康检查等），不携带客户端地址
+    match ver_cmd & 0x0f {
+        0 => return Ok(None),
+        1 => {}
+        _ => return Err(invalid("unsupported PROXY v2 command")),
+    }
+
+    match family {
+        // TCP/UDP over IPv4
+        0x11 | 0x12 if body.len() >= 12 => {
+            let ip = Ipv4Addr::new(body[0], body[1], body[2], body[3]);
+            let port = u16::from_be_bytes([body[8], body[9]]);
+            Ok(Some(SocketAddr::new(IpAddr::V4(ip), port)))
+        }
+        // TCP/UDP over IPv6
+        0x21 | 0x22 if body.len() >= 36 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(&body[..16]);
+            let port = u16::from_be_bytes([body[32], body[33]]);
+            Ok(Some(SocketAddr::new(IpAddr::V6(Ipv6Addr::from(octets)), port)))
+        }
+        0x11 | 0x12 | 0x21 | 0x22 => Err(invalid("truncated PROXY v2 address block")),
+        // UNSPEC、Unix 等地址族无可用的客户端 IP
+        _ => Ok(None),
+    }
+}
+
+/// 按版本构造发往上游的 PROXY 头部；客户端没有 IP（如 Unix 套接字）时发送 UNKNOWN/UNSPEC
+///
+/// 头部会把整条上游连接绑定到一个客户端，这类连接不能放回连接池给其他客户端使用。
+pub fn build_proxy_header(
+    version: ProxyProtocolVersion,
+    peer: &PeerAddr,
+    local: Option<SocketAddr>,
+) -> Vec<u8> {
+    let addrs = match (peer, local) {
+        (PeerAddr::Tcp(src), Some(dst)) => Some(same_family(*src, dst)),
+        _ => None,
+    };
+    match version {
+        ProxyProtocolVersion::V1 => encode_v1(addrs),
+        ProxyProtocolVersion::V2 => encode_v2(addrs),
+    }
+}
+
+/// 源、目的地址族不同时统一转成 IPv6（IPv4 映射地址）
+fn same_family(src: SocketAddr, dst: SocketAddr) -> (SocketAddr, SocketAddr) {
+    if src.is_ipv4() == dst.is_ipv4() {
+        return (src, dst);
+    }
+    let to_v6 = |addr: SocketAddr| match addr.ip() {
+        IpAddr::V4(v4) => SocketAddr::new(IpAddr::V6(v4.to_ipv6_mapped()), addr.port()),
+        IpAddr::V6(_) => addr,
+    };
+    (to_v6(src), to_v6(dst))
+}
+
+fn encode_v1(addrs: Option<(SocketAddr, SocketAddr)>) -> Vec<u8> {
+    match addrs {
+        Some((src, dst)) => {
+            let proto = if src.is_ipv4() { "TCP4" } else { "TCP6" };
+            format!(
+                "PROXY {} {} {} {} {}\r\n",
+                proto,
+                src.ip(),
+                dst.ip(),
+                src.port(),
+                dst.port()
+            )
+            .into_bytes()
+        }
+        None => b"PROXY UNKNOWN\r\n".to_vec(),
+    }
+}
+
+fn encode_v2(addrs: Option<(SocketAddr, SocketAddr)>) -> Vec<u8> {
+    let mut out = Vec::with_capacity(52);
+    out.extend_from_slice(&V2_SIGNATURE);
+    // 版本 2，命令 PROXY
+    out.push(0x21);
+    match addrs {
+        Some((SocketAddr::V4(src), SocketAddr::V4(dst))) => {
+            out.push(0x11);
+            out.extend_from_slice(&12u16.to_be_bytes());
+            out.extend_from_slice(&src.ip().octets());
+            out.extend_from_slice(&dst.ip().octets());
+            out.extend_from_slice(&src.port().to_be_bytes());
+            out.extend_from_slice(&dst.port().to_be_bytes());
+        }
+        Some((SocketAddr::V6(src), SocketAddr::V6(dst))) => {
+            out.push(0x21);
+            out.extend_from_slice(&36u16.to_be_bytes());
+            out.extend_from_slice(&src.ip().octets());
+            out.extend_from_slice(&dst.ip().octets());
+            out.extend_from_slice(&src.port().to_be_bytes());
+            out.extend_from_slice(&dst.port().to_be_bytes());
+        }
+        _ => {
+            // AF_UNSPEC：接收方应使用连接本身的地址
+            out.push(0x00);
+            out.extend_from_slice(&0u16.to_be_bytes());
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn parse(mut bytes: &[u8]) -> Result<Option<SocketAddr>, std::io::Error> {
+        read_proxy_header(&mut bytes).await
+    }
+
+    fn v2(ver_cmd: u8, family: u8, body: &[u8]) -> Vec<u8> {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.push(ver_cmd);
+        header.push(family);
+        header.extend_from_slice(&(body.len() as u16).to_be_bytes());
+        header.extend_from_slice(body);
+        header
+    }
+
+    fn addr(value: &str) -> SocketAddr {
+        value.parse().unwrap()
+    }
+
+    #[tokio::test]
+    async fn parses_v1_addresses_and_leaves_following_data() {
+        let mut bytes: &[u8] = b"PROXY TCP4 192.0.2.1 198.51.100.2 1234 80\r\nGET / HTTP/1.1\r\n";
+        assert_eq!(read_proxy_header(&mut bytes).await.unwrap(), Some(addr("192.0.2.1:1234")));
+        assert_eq!(bytes, b"GET / HTTP/1.1\r\n");
+        assert_eq!(
+            parse(b"PROXY TCP6 2001:db8::1 2001:db8::2 4321 443\r\n").await.unwrap(),
+            Some(addr("[2001:db8::1]:4321"))
+        );
+    }
+
+    #[tokio::test]
+    async fn v1_unknown_carries_no_address() {
+        assert_eq!(parse(b"PROXY UNKNOWN\r\n").await.unwrap(), None);
+        assert_eq!(parse(b"PROXY UNKNOWN 192.0.2.1 198.51.100.2 1234 80\r\n").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn rejects_v1_address_family_mismatch() {
+        assert!(parse(b"PROXY TCP4 2001:db8::1 2001:db8::2 1234 80\r\n").await.is_err());
+        assert!(parse(b"PROXY TCP6 192.0.2.1 198.51.100.2 1234 80\r\n").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_malformed_v1_lines() {
+        assert!(parse(b"PROXY TCP4 192.0.2.1 198.51.100.2 1234\r\n").await.is_err());
+        assert!(parse(b"PROXY TCP4 192.0.2.1 198.51.100.2 http 80\r\n").await.is_err());
+        assert!(parse(b"PROXY TCP4 192.0.2.1").await.is_err());
+        assert!(parse(b"GET / HTTP/1.1\r\n\r\n").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn limits_v1_line_length() {
+        // 含 "PROXY " 与 CRLF 恰好 107 字节时接受，再多一个字节即拒绝
+        let line = |len: usize| {
+            let mut line = b"PROXY UNKNOWN ".to_vec();
+            line.resize(len - 2, b'x');
+            line.extend_from_slice(b"\r\n");
+            line
+        };
+        assert_eq!(parse(&line(V1_MAX_LEN)).await.unwrap(), None);
+        let error = parse(&line(V1_MAX_LEN + 1)).await.unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn parses_v2_addresses() {
+        let mut body = vec![192, 0, 2, 1, 198, 51, 100, 2];
+        body.extend_from_slice(&1234u16.to_be_bytes());
+        body.extend_from_slice(&80u16.to_be_bytes());
+        assert_eq!(parse(&v2(0x21, 0x11, &body)).await.unwrap(), Some(addr("192.0.2.1:1234")));
+
+        let mut body = "2001:db8::1".parse::<Ipv6Addr>().unwrap().octets().to_vec();
+        body.extend_from_slice(&[0; 16]);
+        body.extend_from_slice(&4321u16.to_be_bytes());
+        body.extend_from_slice(&443u16.to_be_bytes());
+        assert_eq!(parse(&v2(0x21, 0x21, &body)).await.unwrap(), Some(addr("[2001:db8::1]:4321")));
+    }
+
+    #[tokio::test]
+    async fn v2_local_and_unspec_carry_no_address() {
+        let mut bytes: &[u8] = &[v2(0x20, 0x11, &[0; 12]), b"GET".to_vec()].concat();
+        assert_eq!(read_proxy_header(&mut bytes).await.unwrap(), None);
+        assert_eq!(bytes, b"GET");
+        assert_eq!(parse(&v2(0x21, 0x00, &[])).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn rejects_truncated_v2_headers() {
+        assert!(parse(&v2(0x21, 0x11, &[192, 0, 2, 1])).await.is_err());
+        assert!(parse(&v2(0x21, 0x21, &[0; 12])).await.is_err());
+        // 声明的长度超过实际数据
+        let mut header = v2(0x21, 0x11, &[0; 12]);
+        header.truncate(header.len() - 4);
+        assert!(parse(&header).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_unsupported_v2_version_and_command() {
+        assert!(parse(&v2(0x11, 0x11, &[0; 12])).await.is_err());
+        assert!(parse(&v2(0x22, 0x11, &[0; 12])).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn encoded_headers_round_trip() {
+        let src = addr("192.0.2.1:1234");
+        let dst = addr("[2001:db8::2]:80");
+        let peer = PeerAddr::Tcp(src);
+        for version in [ProxyProtocolVersion::V1, ProxyProtocolVersion::V2] {
+            let header = build_proxy_header(version, &peer, Some(dst));
+            let mapped = addr("[::ffff:192.0.2.1]:1234");
+            assert_eq!(parse(&header).await.unwrap(), Some(mapped));
+            let header = build_proxy_header(version, &peer, None);
+            assert_eq!(parse(&header).await.unwrap(), None);
+        }
+    }
+}
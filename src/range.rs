@@ -0,0 +1,179 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// 单个请求最多接受的区间数，超过时忽略 Range 返回完整内容
+const MAX_RANGES: usize = 64;
+
+/// Range 头部的处理结果
+pub enum RangeRequest {
+    /// 没有可用的 Range，返回完整内容
+    Full,
+    /// 满足条件的区间，按起始位置排序且互不重叠、互不相邻，均为闭区间 [start, end]
+    Partial(Vec<(u64, u64)>),
+    /// 所有区间都超出文件范围，返回 416
+    Unsatisfiable,
+}
+
+/// 按文件长度解析 Range 头部
+///
+/// 单位不是 bytes 或语法错误时按 RFC 9110 忽略该头部。
+pub fn parse_range(value: &str, len: u64) -> RangeRequest {
+    let Some(specs) = value.trim().strip_prefix("bytes=") else {
+        return RangeRequest::Full;
+    };
+    let mut ranges = Vec::new();
+    let mut count = 0;
+    for spec in specs.split(',') {
+        let spec = spec.trim_matches([' ', '\t']);
+        if spec.is_empty() {
+            continue;
+        }
+        count += 1;
+        if count > MAX_RANGES {
+            return RangeRequest::Full;
+        }
+        let Some((first, last)) = spec.split_once('-') else {
+            return RangeRequest::Full;
+        };
+        let range = if first.is_empty() {
+            // "-N"：最后 N 个字节
+            let Some(suffix) = parse_pos(last) else {
+                return RangeRequest::Full;
+            };
+            (suffix > 0 && len > 0).then(|| (len - suffix.min(len), len - 1))
+        } else {
+            let Some(start) = parse_pos(first) else {
+                return RangeRequest::Full;
+            };
+            let end = if last.is_empty() {
+                u64::MAX
+            } else {
+                match parse_pos(last) {
+                    Some(end) if end >= start => end,
+                    _ => return RangeRequest::Full,
+                }
+            };
+            (start < len).then(|| (start, end.min(len - 1)))
+        };
+        ranges.extend(range);
+    }
+    if count == 0 {
+        RangeRequest::Full
+    } else if ranges.is_empty() {
+        RangeRequest::Unsatisfiable
+    } else {
+        RangeRequest::Partial(coalesce(ranges))
+    }
+}
+
+/// 合并重叠或相邻的区间，避免重复区间把响应放大到文件长度的许多倍
+fn coalesce(mut ranges: Vec<(u64, u64)>) -> Vec<(u64, u64)> {
+    ranges.sort_unstable();
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if start <= last.1.saturating_add(1) => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
+
+fn parse_pos(value: &str) -> Option<u64> {
+    if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    value.parse().ok()
+}
+
+/// multipart/byteranges 响应体的各部分：每段的分隔头与区间，以及结尾分隔符
+pub struct Multipart {
+    pub boundary: String,
+    pub parts: Vec<(String, u64, u64)>,
+    pub trailer: String,
+}
+
+impl Multipart {
+    pub fn new(ranges: &[(u64, u64)], content_type: &str, len: u64) -> Self {
+        let boundary = make_boundary();
+        let parts = ranges
+            .iter()
+            .map(|&(start, end)| {
+                let head = format!(
+                    "\r\n--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+                    boundary, content_type, start, end, len
+                );
+                (head, start, end)
+            })
+            .collect();
+        let trailer = format!("\r\n--{}--\r\n", boundary);
+        Self {
+            boundary,
+            parts,
+            trailer,
+        }
+    }
+
+    /// 整个响应体的字节数
+    pub fn content_length(&self) -> u64 {
+        let parts: u64 = self
+            .parts
+            .iter()
+            .map(|(head, start, end)| head.len() as u64 + end - start + 1)
+            .sum();
+        parts + self.trailer.len() as u64
+    }
+}
+
+/// 生成不会出现在文件内容分隔头之外的分隔符
+fn make_boundary() -> String {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos());
+    format!("{:016x}{:08x}", nanos as u64, std::process::id())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn partial(value: &str, len: u64) -> Option<Vec<(u64, u64)>> {
+        match parse_range(value, len) {
+            RangeRequest::Partial(ranges) => Some(ranges),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn parses_single_ranges() {
+        assert_eq!(partial("bytes=0-99", 1000), Some(vec![(0, 99)]));
+        assert_eq!(partial("bytes=990-2000", 1000), Some(vec![(990, 999)]));
+        assert_eq!(partial("bytes=500-", 1000), Some(vec![(500, 999)]));
+        assert_eq!(partial("bytes=-100", 1000), Some(vec![(900, 999)]));
+        assert_eq!(partial("bytes=-5000", 1000), Some(vec![(0, 999)]));
+    }
+
+    #[test]
+    fn ignores_invalid_specs() {
+        assert!(matches!(parse_range("bytes=5-2", 1000), RangeRequest::Full));
+        assert!(matches!(parse_range("items=0-1", 1000), RangeRequest::Full));
+        assert!(matches!(parse_range("bytes=a-b", 1000), RangeRequest::Full));
+        assert!(matches!(parse_range("bytes=+1-2", 1000), RangeRequest::Full));
+        assert!(matches!(parse_range("bytes=", 1000), RangeRequest::Full));
+    }
+
+    #[test]
+    fn reports_unsatisfiable_ranges() {
+        assert!(matches!(parse_range("bytes=1000-", 1000), RangeRequest::Unsatisfiable));
+        assert!(matches!(parse_range("bytes=-0", 1000), RangeRequest::Unsatisfiable));
+        assert!(matches!(parse_range("bytes=0-0", 0), RangeRequest::Unsatisfiable));
+        // 只要有一个区间可满足就返回 206
+        assert_eq!(partial("bytes=2000-3000,0-0", 1000), Some(vec![(0, 0)]));
+    }
+
+    #[test]
+    fn merges_overlapping_and_adjacent_ranges() {
+        assert_eq!(partial("bytes=0-,0-,0-,0-", 1200), Some(vec![(0, 1199)]));
+        assert_eq!(partial("bytes=500-599,0-99,50-149", 1000), Some(vec![(0, 149), (500, 599)]));
+        assert_eq!(partial("bytes=0-9,10-19", 1000), Some(vec![(0, 19)]));
+        assert_eq!(partial("bytes=-100,0-", 1000), Some(vec![(0, 999)]));
+        assert_eq!(partial("bytes=0-9,20-29", 1000), Some(vec![(0, 9), (20, 29)]));
+    }
+}
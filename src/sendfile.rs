@@ -0,0 +1,108 @@
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+use crate::compress::CompressedBody;
+use crate::stream::{ClientStream, TimeoutStream};
+
+/// 缓冲复制时每次读取的字节数
+const COPY_BUFFER_SIZE: usize = 64 * 1024;
+
+/// 把文件中 [offset, offset + len) 的内容发给客户端
+///
+/// zero_copy 为 true 时在 Linux 上使用 sendfile(2)，每次最多发送 chunk 字节（0 表示不限制）；
+/// 其它平台使用固定大小的缓冲区复制。需要压缩的正文改用 send_file_compressed。
+pub async fn send_file(
+    stream: &mut TimeoutStream<ClientStream>,
+    file: &mut File,
+    offset: u64,
+    len: u64,
+    zero_copy: bool,
+    chunk: u64,
+) -> Result<(), std::io::Error> {
+    #[cfg(target_os = "linux")]
+    if zero_copy {
+        return send_file_zero_copy(stream, file, offset, len, chunk).await;
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = (zero_copy, chunk);
+
+    copy_file(stream, file, offset, len).await
+}
+
+/// 用有界缓冲区分块读取文件并写给客户端
+async fn copy_file(
+    stream: &mut TimeoutStream<ClientStream>,
+    file: &mut File,
+    offset: u64,
+    len: u64,
+) -> Result<(), std::io::Error> {
+    file.seek(std::io::SeekFrom::Start(offset)).await?;
+    let mut buffer = vec![0u8; COPY_BUFFER_SIZE.min(len as usize)];
+    let mut remaining = len;
+    while remaining > 0 {
+        let want = buffer.len().min(remaining as usize);
+        let n = file.read(&mut buffer[..want]).await?;
+        if n == 0 {
+            return Err(truncated());
+        }
+        stream.write_all(&buffer[..n]).await?;
+        remaining -= n as u64;
+    }
+    Ok(())
+}
+
+/// 把整个文件压缩后以 chunked 编码发给客户端；压缩需要读出内容，因此总是缓冲复制
+pub async fn send_file_compressed(
+    stream: &mut TimeoutStream<ClientStream>,
+    file: &mut File,
+    len: u64,
+    mut body: CompressedBody,
+) -> Result<(), std::io::Error> {
+    file.seek(std::io::SeekFrom::Start(0)).await?;
+    let mut buffer = vec![0u8; COPY_BUFFER_SIZE.min(len as usize)];
+    let mut remaining = len;
+    while remaining > 0 {
+        let want = buffer.len().min(remaining as usize);
+        let n = file.read(&mut buffer[..want]).await?;
+        if n == 0 {
+            return Err(truncated());
+        }
+        body.write(stream, &buffer[..n]).await?;
+        remaining -= n as u64;
+    }
+    body.finish(stream).await
+}
+
+/// 用 sendfile(2) 在内核中直接把文件内容写入套接字
+#[cfg(target_os = "linux")]
+async fn send_file_zero_copy(
+    stream: &mut TimeoutStream<ClientStream>,
+    file: &File,
+    offset: u64,
+    len: u64,
+    chunk: u64,
+) -> Result<(), std::io::Error> {
+    let send_timeout = stream.write_timeout();
+    let mut offset = offset as libc::off_t;
+    let mut remaining = len;
+    while remaining > 0 {
+        let count = if chunk > 0 { remaining.min(chunk) } else { remaining } as usize;
+        let send = stream.get_ref().sendfile(file, &mut offset, count);
+        let n = match send_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, send)
+                .await
+                .map_err(|_| std::io::Error::new(std::io::ErrorKind::TimedOut, "write timed out"))??,
+            None => send.await?,
+        };
+        if n == 0 {
+            return Err(truncated());
+        }
+        remaining -= n as u64;
+    }
+    Ok(())
+}
+
+/// 文件在发送过程中被截短
+fn truncated() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "file truncated while sending")
+}
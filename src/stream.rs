@@ -0,0 +1,280 @@
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+#[cfg(unix)]
+use tokio::net::UnixStream;
+use tokio::time::{Sleep, sleep};
+
+/// 客户端连接：TCP 或 Unix 域套接字，对上层统一提供读写
+pub enum ClientStream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+/// 客户端地址，用于日志输出
+#[derive(Debug, Clone)]
+pub enum PeerAddr {
+    Tcp(SocketAddr),
+    /// Unix 套接字对端通常没有地址，记录对端进程凭据
+    Unix { pid: Option<i32>, uid: Option<u32> },
+}
+
+impl fmt::Display for PeerAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PeerAddr::Tcp(addr) => write!(f, "{}", addr),
+            PeerAddr::Unix { pid, uid } => {
+                write!(f, "unix:")?;
+                if let Some(pid) = pid {
+                    write!(f, "pid={}", pid)?;
+                }
+                if let Some(uid) = uid {
+                    let sep = if pid.is_some() { "," } else { "" };
+                    write!(f, "{}uid={}", sep, uid)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl ClientStream {
+    /// 客户端连接到的本地地址，Unix 套接字返回 None
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        match self {
+            ClientStream::Tcp(s) => s.local_addr().ok(),
+            #[cfg(unix)]
+            ClientStream::Unix(_) => None,
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl ClientStream {
+    /// 调用 sendfile(2) 从 offset 处发送最多 count 字节，等待套接字可写后返回实际发送的字节数
+    pub async fn sendfile(
+        &self,
+        file: &impl std::os::fd::AsRawFd,
+        offset: &mut libc::off_t,
+        count: usize,
+    ) -> io::Result<usize> {
+        use std::os::fd::AsRawFd;
+        use tokio::io::Interest;
+
+        let in_fd = file.as_raw_fd();
+        let send = |out_fd: i32, offset: &mut libc::off_t| {
+            // SAFETY: 两个 fd 在调用期间都由调用方持有，offset 指向有效的 off_t
+            let n = unsafe { libc::sendfile(out_fd, in_fd, offset, count) };
+            if n < 0 {
+                Err(io::Error::last_os_error())
+            } else {
+                Ok(n as usize)
+            }
+        };
+        loop {
+            let result = match self {
+                ClientStream::Tcp(s) => {
+                    s.writable().await?;
+                    s.try_io(Interest::WRITABLE, || send(s.as_raw_fd(), offset))
+                }
+                ClientStream::Unix(s) => {
+                    s.writable().await?;
+                    s.try_io(Interest::WRITABLE, || send(s.as_raw_fd(), offset))
+                }
+            };
+            match result {
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                result => return result,
+            }
+        }
+    }
+}
+
+impl PeerAddr {
+    /// 客户端 IP，Unix 套接字对端没有 IP
+    pub fn ip(&self) -> Option<IpAddr> {
+        match self {
+            PeerAddr::Tcp(addr) => Some(addr.ip()),
+            PeerAddr::Unix { .. } => None,
+        }
+    }
+}
+
+#[cfg(unix)]
+impl PeerAddr {
+    /// 从 Unix 连接读取对端凭据，读取失败时只记录 "unix:"
+    pub fn from_unix(stream: &UnixStream) -> Self {
+        match stream.peer_cred() {
+            Ok(cred) => PeerAddr::Unix {
+                pid: cred.pid(),
+                uid: Some(cred.uid()),
+            },
+            Err(_) => PeerAddr::Unix { pid: None, uid: None },
+        }
+    }
+}
+
+impl AsyncRead for ClientStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ClientStream::Tcp(s) => Pin::new(s).poll_read(cx, buf),
+            #[cfg(unix)]
+            ClientStream::Unix(s) => Pin::new(s).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for ClientStream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            ClientStream::Tcp(s) => Pin::new(s).poll_write(cx, buf),
+            #[cfg(unix)]
+            ClientStream::Unix(s) => Pin::new(s).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ClientStream::Tcp(s) => Pin::new(s).poll_flush(cx),
+            #[cfg(unix)]
+            ClientStream::Unix(s) => Pin::new(s).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ClientStream::Tcp(s) => Pin::new(s).poll_shutdown(cx),
+            #[cfg(unix)]
+            ClientStream::Unix(s) => Pin::new(s).poll_shutdown(cx),
+        }
+    }
+}
+
+/// 带读写空闲超时的流：两次成功读（或写）之间超过时限时返回 TimedOut
+pub struct TimeoutStream<S> {
+    inner: S,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    read_deadline: Option<Pin<Box<Sleep>>>,
+    write_deadline: Option<Pin<Box<Sleep>>>,
+}
+
+impl<S> TimeoutStream<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            read_timeout: None,
+            write_timeout: None,
+            read_deadline: None,
+            write_deadline: None,
+        }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    /// 设置读超时，None 表示不限制；正在进行的等待按新时限重新计时
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.read_timeout = timeout;
+        self.read_deadline = None;
+    }
+
+    pub fn write_timeout(&self) -> Option<Duration> {
+        self.write_timeout
+    }
+
+    pub fn set_write_timeout(&mut self, timeout: Option<Duration>) {
+        self.write_timeout = timeout;
+        self.write_deadline = None;
+    }
+}
+
+/// 操作仍未就绪时启动或检查计时器，到期返回 TimedOut
+fn poll_deadline(
+    deadline: &mut Option<Pin<Box<Sleep>>>,
+    timeout: Option<Duration>,
+    what: &'static str,
+    cx: &mut Context<'_>,
+) -> Poll<io::Result<()>> {
+    let Some(timeout) = timeout else {
+        return Poll::Pending;
+    };
+    let sleep = deadline.get_or_insert_with(|| Box::pin(sleep(timeout)));
+    match sleep.as_mut().poll(cx) {
+        Poll::Ready(()) => {
+            *deadline = None;
+            Poll::Ready(Err(io::Error::new(io::ErrorKind::TimedOut, what)))
+        }
+        Poll::Pending => Poll::Pending,
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for TimeoutStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        match Pin::new(&mut this.inner).poll_read(cx, buf) {
+            Poll::Ready(result) => {
+                this.read_deadline = None;
+                Poll::Ready(result)
+            }
+            Poll::Pending => poll_deadline(&mut this.read_deadline, this.read_timeout, "read timed out", cx),
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for TimeoutStream<S> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        match Pin::new(&mut this.inner).poll_write(cx, buf) {
+            Poll::Ready(result) => {
+                this.write_deadline = None;
+                Poll::Ready(result)
+            }
+            Poll::Pending => {
+                poll_deadline(&mut this.write_deadline, this.write_timeout, "write timed out", cx).map(|r| r.map(|()| 0))
+            }
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        match Pin::new(&mut this.inner).poll_flush(cx) {
+            Poll::Ready(result) => {
+                this.write_deadline = None;
+                Poll::Ready(result)
+            }
+            Poll::Pending => poll_deadline(&mut this.write_deadline, this.write_timeout, "write timed out", cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        match Pin::new(&mut this.inner).poll_shutdown(cx) {
+            Poll::Ready(result) => {
+                this.write_deadline = None;
+                Poll::Ready(result)
+            }
+            Poll::Pending => poll_deadline(&mut this.write_deadline, this.write_timeout, "write timed out", cx),
+        }
+    }
+}
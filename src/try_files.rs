@@ -0,0 +1,57 @@
+use tokio::fs;
+
+use crate::path::{normalize_path, percent_encode_path};
+
+/// try_files 的查找结果
+pub enum TryFiles<'a> {
+    /// 按静态文件处理该路径
+    Path(String),
+    /// 直接返回该状态码
+    Status(u16),
+    /// 转发给命名上游（upstreams 中以 @ 开头的键）
+    Upstream(&'a str),
+}
+
+/// 按顺序检查 try_files 条目，uri 为规范化后的请求路径
+///
+/// 除最后一项外，每项展开 $uri 后检查文件是否存在，以 / 结尾的检查目录；
+/// 最后一项是兜底：=code 返回状态码，@name 转发给命名上游，其它作为要发送的路径。
+pub async fn resolve<'a>(root: &str, entries: &'a [String], uri: &str) -> TryFiles<'a> {
+    let Some((fallback, candidates)) = entries.split_last() else {
+        return TryFiles::Path(uri.to_string());
+    };
+    for entry in candidates {
+        let Some(path) = expand(entry, uri) else {
+            continue;
+        };
+        let found = match fs::metadata(format!("{}{}", root, path)).await {
+            Ok(meta) if path.ends_with('/') => meta.is_dir(),
+            Ok(meta) => meta.is_file(),
+            Err(_) => false,
+        };
+        if found {
+            return TryFiles::Path(path);
+        }
+    }
+
+    if let Some(code) = fallback.strip_prefix('=') {
+        return TryFiles::Status(parse_status(code).unwrap_or(404));
+    }
+    if fallback.starts_with('@') {
+        return TryFiles::Upstream(fallback);
+    }
+    match expand(fallback, uri) {
+        Some(path) => TryFiles::Path(path),
+        None => TryFiles::Status(404),
+    }
+}
+
+/// 解析 "=code" 中的状态码，只接受 200-599 的最终状态码
+pub fn parse_status(code: &str) -> Option<u16> {
+    code.parse().ok().filter(|code| (200..=599).contains(code))
+}
+
+/// 把条目中的 $uri 替换为请求路径并重新规范化；请求路径先编码，避免被解码两次
+fn expand(entry: &str, uri: &str) -> Option<String> {
+    normalize_path(&entry.replace("$uri", &percent_encode_path(uri)))
+}
@@ -1,9 +1,19 @@
 use std::sync::Arc;
 
-use crate::config::load_config;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tokio::time::{self, Duration};
+
+use crate::config::{AppConfig, ListenConfig, load_config};
 use crate::handler::handle_client;
-use crate::listener::create_listener;
+use crate::listener::{Listener, inherited_fds, open_listener, tune_accepted_stream};
 use crate::pool::ConnectionPool;
+use crate::proxy_protocol::accept_proxy_protocol;
+
+/// fd 或内存耗尽时暂停 accept 的时长，等待已有连接释放资源
+const ACCEPT_EXHAUSTED_DELAY: Duration = Duration::from_millis(500);
+/// 其他未知 accept 错误后的退避时长，避免空转刷日志
+const ACCEPT_ERROR_DELAY: Duration = Duration::from_millis(50);
 
 /// worker 进程：加载配置、初始化连接池并处理请求
 pub async fn run_worker_process() -> Result<(), Box<dyn std::error::Error>> {
@@ -11,23 +21,166 @@ pub async fn run_worker_process() -> Result<(), Box<dyn std::error::Error>> {
     let config = load_config("config.json").await?;
     let shared_config = Arc::new(config);
 
+    if let Some(limit) = shared_config.worker_rlimit_nofile {
+        raise_nofile_limit(limit);
+    }
+
     // 初始化连接池，参数来自配置
     let connection_pool = ConnectionPool::new_with_config(&shared_config.pool);
 
-    let addr = shared_config.listen_addr.as_str();
-    let listener = create_listener(addr)?;
+    // 先打开全部监听器（优先使用 master 继承的 fd），任一失败则整个 worker 启动失败
+    let mut inherited = inherited_fds();
+    let mut listeners = Vec::with_capacity(shared_config.listen.len());
+    for listen in &shared_config.listen {
+        listeners.push((listen.clone(), open_listener(listen, &mut inherited)?));
+    }
 
     let id = std::process::id();
-    println!("Worker [{}] started on {}", id, addr);
+    let addrs: Vec<&str> = listeners.iter().map(|(listen, _)| listen.addr.as_str()).collect();
+    println!("Worker [{}] started on {}", id, addrs.join(", "));
+
+    // 所有监听器共享同一个连接数上限
+    let connections = Arc::new(Semaphore::new(shared_config.worker_connections));
+
+    // 每个监听器一个 accept 循环，并发运行
+    let mut accept_loops = JoinSet::new();
+    for (listen, listener) in listeners {
+        accept_loops.spawn(accept_loop(
+            Arc::new(listen),
+            listener,
+            shared_config.clone(),
+            connection_pool.clone(),
+            connections.clone(),
+        ));
+    }
+
+    // accept 循环本身不会退出，只有任务 panic 才会走到这里
+    match accept_loops.join_next().await {
+        Some(Err(e)) => Err(e.into()),
+        Some(Ok(())) | None => Ok(()),
+    }
+}
 
-    // 主循环：接受连接并交给异步任务处理
+/// 单个监听器的主循环：接受连接并交给异步任务处理
+async fn accept_loop(
+    listen: Arc<ListenConfig>,
+    listener: Listener,
+    config: Arc<AppConfig>,
+    pool: ConnectionPool,
+    connections: Arc<Semaphore>,
+) {
     loop {
-        let (stream, _) = listener.accept().await?;
-        let config_clone = shared_config.clone();
+        let (mut stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                if let Some(delay) = accept_error_delay(&e) {
+                    eprintln!(
+                        "Worker: accept on {} failed: {}, retrying in {:?}",
+                        listen.addr, e, delay
+                    );
+                    time::sleep(delay).await;
+                }
+                continue;
+            }
+        };
+        // 接受连接后再占名额，空闲的监听器不占名额；名额耗尽时持有该连接等待，暂停本监听器的 accept
+        let permit = match connections.clone().try_acquire_owned() {
+            Ok(permit) => permit,
+            Err(_) => {
+                eprintln!(
+                    "Worker: worker_connections ({}) reached, pausing accept on {}",
+                    config.worker_connections, listen.addr
+                );
+                match connections.clone().acquire_owned().await {
+                    Ok(permit) => permit,
+                    Err(_) => return,
+                }
+            }
+        };
+
+        // 按监听项调整新连接的套接字选项
+        tune_accepted_stream(&stream, &listen);
+        let config_clone = config.clone();
         // 克隆连接池句柄（内部为 Arc，成本低）
-        let pool_clone = connection_pool.clone();
+        let pool_clone = pool.clone();
+        let listen_clone = listen.clone();
         tokio::spawn(async move {
-            handle_client(stream, config_clone, pool_clone).await;
+            // 经 L4 负载均衡转发时，先从 PROXY 头部取出真实客户端地址；头部同样受 client_header_timeout 限制
+            let peer = if listen_clone.proxy_protocol {
+                let header_timeout = Duration::from_secs(config_clone.client_header_timeout);
+                match time::timeout(header_timeout, accept_proxy_protocol(&mut stream, peer.clone(), &listen_clone)).await {
+                    Ok(Some(peer)) => peer,
+                    Ok(None) => return,
+                    Err(_) => {
+                        eprintln!("PROXY protocol: header timeout from {} on {}", peer, listen_clone.addr);
+                        return;
+                    }
+                }
+            } else {
+                peer
+            };
+            handle_client(stream, peer, config_clone, pool_clone).await;
+            drop(permit);
         });
     }
 }
+
+/// 根据 accept 错误类型决定退避时长；返回 None 表示单个连接的问题，直接重试
+fn accept_error_delay(e: &std::io::Error) -> Option<Duration> {
+    use std::io::ErrorKind;
+
+    match e.kind() {
+        // 对端在握手完成前断开等，只影响该连接
+        ErrorKind::ConnectionAborted
+        | ErrorKind::ConnectionReset
+        | ErrorKind::Interrupted
+        | ErrorKind::WouldBlock => return None,
+        _ => {}
+    }
+    #[cfg(unix)]
+    if matches!(
+        e.raw_os_error(),
+        Some(libc::EMFILE | libc::ENFILE | libc::ENOBUFS | libc::ENOMEM)
+    ) {
+        return Some(ACCEPT_EXHAUSTED_DELAY);
+    }
+    Some(ACCEPT_ERROR_DELAY)
+}
+
+/// 提升 RLIMIT_NOFILE；超过硬限制且无权提升时退回到硬限制
+#[cfg(unix)]
+fn raise_nofile_limit(limit: u64) {
+    // SAFETY: 仅读写本地的 rlimit 结构体
+    unsafe {
+        let mut rlim: libc::rlimit = std::mem::zeroed();
+        if libc::getrlimit(libc::RLIMIT_NOFILE, &mut rlim) != 0 {
+            eprintln!("Worker: getrlimit(RLIMIT_NOFILE) failed: {}", std::io::Error::last_os_error());
+            return;
+        }
+        let wanted = limit as libc::rlim_t;
+        let mut target = libc::rlimit {
+            rlim_cur: wanted,
+            rlim_max: rlim.rlim_max.max(wanted),
+        };
+        if libc::setrlimit(libc::RLIMIT_NOFILE, &target) != 0 {
+            let err = std::io::Error::last_os_error();
+            target = libc::rlimit {
+                rlim_cur: wanted.min(rlim.rlim_max),
+                rlim_max: rlim.rlim_max,
+            };
+            if libc::setrlimit(libc::RLIMIT_NOFILE, &target) != 0 {
+                eprintln!("Worker: setrlimit(RLIMIT_NOFILE, {}) failed: {}", limit, err);
+                return;
+            }
+            eprintln!(
+                "Worker: RLIMIT_NOFILE capped at hard limit {} ({})",
+                target.rlim_cur, err
+            );
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn raise_nofile_limit(_limit: u64) {
+    eprintln!("Worker: worker_rlimit_nofile is only supported on Unix");
+}